@@ -0,0 +1,184 @@
+use std::collections::BTreeMap;
+
+use crate::input::{self, Token, TokenKind};
+
+/// Aliases and functions that stand for longer command lines
+#[derive(Debug, Clone, Default)]
+pub struct Aliases {
+    /// Words replaced by a command line, such as `ll` for `ls -lah`
+    pub aliases: BTreeMap<String, String>,
+    /// Command lines run with `$1`, `$2`, ... and `$@` standing for their arguments
+    pub functions: BTreeMap<String, String>,
+}
+
+impl Aliases {
+    /// Expand the aliases and functions a command line starts commands with
+    ///
+    /// Only the first word of each command is looked up, and only when it
+    /// is unquoted, so `\ll` or `"ll"` runs the real program. A function
+    /// takes the rest of its command as arguments. An alias or function
+    /// is not expanded again inside its own expansion.
+    pub fn expand_line(&self, line: &str) -> String {
+        self.expand(line, &mut Vec::new())
+    }
+
+    /// Describe what the command starting at a token expands to, for hover previews
+    ///
+    /// Returns whether the token names an alias or a function, with the
+    /// expansion of its command. Returns `None` if the token doesn't start
+    /// a command or isn't defined.
+    pub fn describe(&self, tokens: &[Token], idx: usize) -> Option<(&'static str, String)> {
+        let token = tokens.get(idx).filter(|token| token.kind == TokenKind::Word)?;
+        let command_start = idx.checked_sub(1).is_none_or(|prev| starts_command(&tokens[prev]));
+        if !command_start {
+            return None;
+        }
+
+        let kind = if self.aliases.contains_key(&token.text) {
+            "alias"
+        } else if self.functions.contains_key(&token.text) {
+            "function"
+        } else {
+            return None;
+        };
+
+        // Expand only this command, up to the next operator
+        let end = tokens[idx..].iter()
+            .position(|token| token.kind == TokenKind::Operator)
+            .map_or(tokens.len(), |pos| idx + pos);
+        let command: Vec<&str> = tokens[idx..end].iter().map(|token| token.text.as_str()).collect();
+        Some((kind, self.expand_line(&command.join(" "))))
+    }
+
+    /// Expand a line, skipping the names already being expanded
+    fn expand(&self, line: &str, active: &mut Vec<String>) -> String {
+        // Lines that don't tokenize are left for the parser to report
+        let Ok(tokens) = input::tokenize(line) else {
+            return line.to_string();
+        };
+
+        let mut expanded = String::new();
+        let mut copied = 0;
+        let mut command_start = true;
+        let mut idx = 0;
+
+        while let Some(token) = tokens.get(idx) {
+            if token.kind == TokenKind::Operator {
+                command_start = starts_command(token);
+                idx += 1;
+                continue;
+            }
+
+            let name = &token.text;
+            let replacement = if !command_start || active.contains(name) {
+                None
+            } else if let Some(value) = self.aliases.get(name) {
+                Some((value.clone(), idx + 1))
+            } else if let Some(body) = self.functions.get(name) {
+                // The arguments run up to the next operator
+                let end = tokens[idx + 1..].iter()
+                    .position(|token| token.kind == TokenKind::Operator)
+                    .map_or(tokens.len(), |pos| idx + 1 + pos);
+                let args: Vec<&str> = tokens[idx + 1..end].iter().map(|token| token.text.as_str()).collect();
+                Some((substitute_args(body, &args), end))
+            } else {
+                None
+            };
+
+            command_start = false;
+            let Some((value, next)) = replacement else {
+                idx += 1;
+                continue;
+            };
+
+            expanded.push_str(&line[copied..token.range.0]);
+            active.push(name.clone());
+            expanded.push_str(&self.expand(&value, active));
+            active.pop();
+
+            copied = tokens[next - 1].range.1;
+            idx = next;
+        }
+
+        expanded.push_str(&line[copied..]);
+        expanded
+    }
+}
+
+/// Check whether the word after an operator is a command, as after `|` or `&&`
+fn starts_command(operator: &Token) -> bool {
+    matches!(operator.text.as_str(), "|" | "&&" | "||" | ";" | "&")
+}
+
+/// Replace `$1` to `$9`, `$@`, `$*` and `$#` in a function body
+///
+/// Arguments go in as typed, quotes included, so they are written unquoted
+/// in the body. Other variables are left for expansion when the command
+/// runs, and nothing is replaced inside single quotes.
+fn substitute_args(body: &str, args: &[&str]) -> String {
+    let mut result = String::new();
+    let mut chars = body.chars().peekable();
+    let mut single_quoted = false;
+    let mut double_quoted = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' if !double_quoted => single_quoted = !single_quoted,
+            '"' if !single_quoted => double_quoted = !double_quoted,
+            '\\' if !single_quoted => {
+                result.push(c);
+                if let Some(escaped) = chars.next() {
+                    result.push(escaped);
+                }
+                continue;
+            }
+            '$' if !single_quoted => {
+                let replacement = match chars.peek() {
+                    Some('@' | '*') => Some(args.join(" ")),
+                    Some('#') => Some(args.len().to_string()),
+                    Some(digit @ '1'..='9') => {
+                        let n = digit.to_digit(10).unwrap_or_default() as usize;
+                        Some(args.get(n - 1).copied().unwrap_or_default().to_string())
+                    }
+                    _ => None,
+                };
+                if let Some(replacement) = replacement {
+                    chars.next();
+                    result.push_str(&replacement);
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        result.push(c);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_line() {
+        let aliases = Aliases {
+            aliases: BTreeMap::from([
+                ("gs".to_string(), "git status".to_string()),
+                ("ll".to_string(), "ls -lah".to_string()),
+                ("ls".to_string(), "ls --color=auto".to_string()),
+            ]),
+            functions: BTreeMap::from([
+                ("mkcd".to_string(), "mkdir -p $1 && cd $1".to_string()),
+                ("say".to_string(), "echo $# '$1' $@".to_string()),
+            ]),
+        };
+
+        assert_eq!(aliases.expand_line("gs -s"), "git status -s");
+        assert_eq!(aliases.expand_line("ll | grep gs"), "ls --color=auto -lah | grep gs");
+        assert_eq!(aliases.expand_line("\"ll\"; echo ll && ll"), "\"ll\"; echo ll && ls --color=auto -lah");
+        assert_eq!(aliases.expand_line("mkcd \"new dir\" > log"), "mkdir -p \"new dir\" && cd \"new dir\" > log");
+        assert_eq!(aliases.expand_line("say a b"), "echo 2 '$1' a b");
+        assert_eq!(aliases.expand_line("echo 'unclosed"), "echo 'unclosed");
+    }
+}
@@ -0,0 +1,152 @@
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+
+/// Turn a line of output with SGR escape sequences into styled spans
+///
+/// Styling starts from `base` at the start of every line, and a reset goes
+/// back to it, so stderr lines keep their color around colored words. Any
+/// other escape sequences are left out.
+pub fn styled_line(text: &str, base: Style) -> Line<'static> {
+    let mut spans = Vec::new();
+    let mut style = base;
+    let mut current = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find('\x1b') {
+        current.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        // Only CSI sequences are expected here, anything else is dropped with the ESC
+        let Some(csi) = rest.strip_prefix('[') else {
+            continue;
+        };
+        let Some(end) = csi.find(|c: char| ('@'..='~').contains(&c)) else {
+            rest = "";
+            break;
+        };
+        rest = &csi[end + 1..];
+
+        if csi[end..].starts_with('m') {
+            if !current.is_empty() {
+                spans.push(Span::styled(std::mem::take(&mut current), style));
+            }
+            style = apply_sgr(style, &csi[..end], base);
+        }
+    }
+
+    current.push_str(rest);
+    if !current.is_empty() {
+        spans.push(Span::styled(current, style));
+    }
+
+    Line::from(spans)
+}
+
+/// Apply the parameters of an SGR sequence, such as `1;31`, to a style
+fn apply_sgr(mut style: Style, params: &str, base: Style) -> Style {
+    // Truecolor is sometimes written with colons, as in `38:2:255:0:0`
+    let codes: Vec<u16> = params.split([';', ':'])
+        .map(|code| code.parse().unwrap_or(0))
+        .collect();
+
+    let mut codes = codes.into_iter();
+    while let Some(code) = codes.next() {
+        style = match code {
+            0 => base,
+            1 => style.add_modifier(Modifier::BOLD),
+            2 => style.add_modifier(Modifier::DIM),
+            3 => style.add_modifier(Modifier::ITALIC),
+            4 => style.add_modifier(Modifier::UNDERLINED),
+            5 => style.add_modifier(Modifier::SLOW_BLINK),
+            7 => style.add_modifier(Modifier::REVERSED),
+            9 => style.add_modifier(Modifier::CROSSED_OUT),
+            22 => style.remove_modifier(Modifier::BOLD | Modifier::DIM),
+            23 => style.remove_modifier(Modifier::ITALIC),
+            24 => style.remove_modifier(Modifier::UNDERLINED),
+            25 => style.remove_modifier(Modifier::SLOW_BLINK),
+            27 => style.remove_modifier(Modifier::REVERSED),
+            29 => style.remove_modifier(Modifier::CROSSED_OUT),
+            30..=37 => style.fg(basic_color(code - 30)),
+            90..=97 => style.fg(basic_color(code - 90 + 8)),
+            40..=47 => style.bg(basic_color(code - 40)),
+            100..=107 => style.bg(basic_color(code - 100 + 8)),
+            38 => match extended_color(&mut codes) {
+                Some(color) => style.fg(color),
+                None => style,
+            },
+            48 => match extended_color(&mut codes) {
+                Some(color) => style.bg(color),
+                None => style,
+            },
+            39 => Style { fg: base.fg, ..style },
+            49 => Style { bg: base.bg, ..style },
+            _ => style,
+        };
+    }
+
+    style
+}
+
+/// Read a 256-color (`5;n`) or truecolor (`2;r;g;b`) color after a 38 or 48
+fn extended_color(codes: &mut impl Iterator<Item = u16>) -> Option<Color> {
+    let mut channel = || codes.next().map(|value| value.min(255) as u8);
+    match channel()? {
+        5 => channel().map(Color::Indexed),
+        2 => Some(Color::Rgb(channel()?, channel()?, channel()?)),
+        _ => None,
+    }
+}
+
+/// Map one of the 16 standard terminal colors to a ratatui color
+fn basic_color(idx: u16) -> Color {
+    match idx {
+        0 => Color::Black,
+        1 => Color::Red,
+        2 => Color::Green,
+        3 => Color::Yellow,
+        4 => Color::Blue,
+        5 => Color::Magenta,
+        6 => Color::Cyan,
+        7 => Color::Gray,
+        8 => Color::DarkGray,
+        9 => Color::LightRed,
+        10 => Color::LightGreen,
+        11 => Color::LightYellow,
+        12 => Color::LightBlue,
+        13 => Color::LightMagenta,
+        14 => Color::LightCyan,
+        _ => Color::White,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_styled_line() {
+        let line = styled_line("\x1b[1;31merror\x1b[0m: \x1b[38;5;208mmismatched\x1b[m \x1b[38;2;1;2;3mtypes", Style::default());
+        let spans: Vec<(&str, Style)> = line.spans.iter().map(|span| (span.content.as_ref(), span.style)).collect();
+
+        assert_eq!(spans, [
+            ("error", Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)),
+            (": ", Style::default()),
+            ("mismatched", Style::default().fg(Color::Indexed(208))),
+            (" ", Style::default()),
+            ("types", Style::default().fg(Color::Rgb(1, 2, 3))),
+        ]);
+    }
+
+    #[test]
+    fn test_styled_line_keeps_base() {
+        let base = Style::default().fg(Color::LightRed);
+        let line = styled_line("\x1b[4;7mx\x1b[24;39my\x1b[0mz\x1b[2J", base);
+        let spans: Vec<(&str, Style)> = line.spans.iter().map(|span| (span.content.as_ref(), span.style)).collect();
+
+        assert_eq!(spans, [
+            ("x", base.add_modifier(Modifier::UNDERLINED | Modifier::REVERSED)),
+            ("y", base.add_modifier(Modifier::REVERSED).remove_modifier(Modifier::UNDERLINED)),
+            ("z", base),
+        ]);
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::input::{self, TokenKind};
+
+/// How long an index of PATH is used before the directories are read again
+const INDEX_MAX_AGE: Duration = Duration::from_secs(30);
+
+/// Most corrections offered for a missing command
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Executables found on PATH, by name
+///
+/// Reading every PATH directory is too slow to do for each command, so the
+/// index is kept until PATH changes or it gets old.
+#[derive(Debug, Default)]
+pub struct PathIndex {
+    /// The PATH the index was built from
+    path: String,
+    /// When the index was built
+    built: Option<Instant>,
+    /// Executables by name, where the first directory on PATH wins
+    commands: BTreeMap<String, PathBuf>,
+}
+
+impl PathIndex {
+    /// Find an executable on PATH
+    ///
+    /// A name missing from the index is looked for in the PATH directories
+    /// directly, so programs installed since the index was built are found.
+    pub fn lookup(&mut self, path: &str, name: &str) -> Option<PathBuf> {
+        self.refresh(path);
+        if let Some(found) = self.commands.get(name) {
+            return Some(found.clone());
+        }
+
+        let found = path.split(':')
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| Path::new(dir).join(name))
+            .find(|candidate| is_executable(candidate))?;
+
+        // The index is out of date, so read it again next time
+        self.built = None;
+        Some(found)
+    }
+
+    /// Get the names of every executable on PATH
+    pub fn names(&mut self, path: &str) -> impl Iterator<Item = &str> {
+        self.refresh(path);
+        self.commands.keys().map(String::as_str)
+    }
+
+    /// Rebuild the index if PATH changed or the index is old
+    fn refresh(&mut self, path: &str) {
+        let fresh = self.built.is_some_and(|built| built.elapsed() < INDEX_MAX_AGE);
+        if fresh && self.path == path {
+            return;
+        }
+
+        self.commands.clear();
+        for dir in path.split(':').filter(|dir| !dir.is_empty()) {
+            let Ok(entries) = fs::read_dir(dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if !self.commands.contains_key(&name) && is_executable(&entry.path()) {
+                    self.commands.insert(name, entry.path());
+                }
+            }
+        }
+
+        self.path = path.to_string();
+        self.built = Some(Instant::now());
+    }
+}
+
+/// Check whether a path is a file that can be run
+fn is_executable(path: &Path) -> bool {
+    let Ok(metadata) = fs::metadata(path) else {
+        return false;
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+    }
+    #[cfg(not(unix))]
+    {
+        metadata.is_file()
+    }
+}
+
+/// Corrections for a command that wasn't found
+#[derive(Debug, Clone, PartialEq)]
+pub struct Suggestions {
+    /// The command line as typed
+    pub command: String,
+    /// The command that wasn't found
+    pub missing: String,
+    /// Known commands close to the missing one, best first
+    pub candidates: Vec<String>,
+}
+
+impl Suggestions {
+    /// Find the known commands closest to a missing one
+    ///
+    /// Commands more than an edit or two away are left out. Ties go to the
+    /// command used most often, according to `usage`, and then to one with
+    /// the same letters, since swapped letters are the most common typo.
+    pub fn new<'a>(
+        command: &str,
+        missing: &str,
+        names: impl IntoIterator<Item = &'a str>,
+        usage: &HashMap<String, usize>,
+    ) -> Self {
+        let max_distance = if missing.chars().count() <= 4 { 1 } else { 2 };
+        let letters = |word: &str| {
+            let mut letters: Vec<char> = word.chars().collect();
+            letters.sort_unstable();
+            letters
+        };
+        let missing_letters = letters(missing);
+
+        let mut ranked: Vec<(usize, usize, bool, &str)> = names.into_iter()
+            .filter(|name| *name != missing)
+            .map(|name| (edit_distance(missing, name), name))
+            .filter(|(distance, _)| *distance <= max_distance)
+            .map(|(distance, name)| (
+                distance,
+                usize::MAX - usage.get(name).copied().unwrap_or(0),
+                letters(name) != missing_letters,
+                name,
+            ))
+            .collect();
+        ranked.sort();
+        ranked.dedup();
+
+        Self {
+            command: command.to_string(),
+            missing: missing.to_string(),
+            candidates: ranked.into_iter()
+                .take(MAX_SUGGESTIONS)
+                .map(|(_, _, _, name)| name.to_string())
+                .collect(),
+        }
+    }
+
+    /// Get the command line with the missing command replaced by a candidate
+    ///
+    /// Falls back to the candidate on its own when the missing command came
+    /// from an alias rather than the typed line.
+    pub fn corrected(&self, idx: usize) -> Option<String> {
+        let candidate = self.candidates.get(idx)?;
+        let tokens = input::tokenize(&self.command).unwrap_or_default();
+
+        let corrected = tokens.iter()
+            .find(|token| token.kind == TokenKind::Word && token.text == self.missing)
+            .map(|token| format!("{}{}{}", &self.command[..token.range.0], candidate, &self.command[token.range.1..]));
+        Some(corrected.unwrap_or_else(|| candidate.clone()))
+    }
+}
+
+/// Count the edits turning one word into another
+///
+/// Inserting, deleting or changing a character, or swapping two adjacent
+/// ones, counts as one edit, so `gti` is one edit from `git`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    // distances[i][j] is the distance between the first i chars of a and the first j of b
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, distance) in distances[0].iter_mut().enumerate() {
+        *distance = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+
+    distances[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suggestions() {
+        assert_eq!(edit_distance("gti", "git"), 1);
+        assert_eq!(edit_distance("pyhton3", "python3"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+
+        let names = ["git", "gitk", "gio", "grep", "python3", "cd", "ll", "ls"];
+        let usage = HashMap::from([("gio".to_string(), 1), ("git".to_string(), 5)]);
+
+        let suggestions = Suggestions::new("gti status | less", "gti", names, &usage);
+        assert_eq!(suggestions.candidates, ["git"]);
+        assert_eq!(suggestions.corrected(0), Some("git status | less".to_string()));
+        assert_eq!(suggestions.corrected(1), None);
+
+        let suggestions = Suggestions::new("gi", "gi", names, &usage);
+        assert_eq!(suggestions.candidates, ["git", "gio"]);
+        assert_eq!(Suggestions::new("pyhton3", "pyhton3", names, &usage).candidates, ["python3"]);
+        assert_eq!(Suggestions::new("sl", "sl", names, &usage).candidates, ["ls", "ll"]);
+    }
+}
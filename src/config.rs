@@ -0,0 +1,323 @@
+use anyhow::Result;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::style::Color;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Application configuration loaded from config.toml
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Command execution settings
+    pub execution: ExecutionConfig,
+    /// Key bindings
+    pub keybindings: KeyBindings,
+    /// Color settings
+    pub colors: ColorConfig,
+    /// Words that stand for longer command lines, such as `ll = "ls -lah"`
+    pub aliases: BTreeMap<String, String>,
+    /// Command lines run by name, with `$1`, `$2`, ... and `$@` for their arguments
+    pub functions: BTreeMap<String, String>,
+}
+
+/// Color settings, with a palette for each theme
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ColorConfig {
+    /// Which palette to use, `dark` or `light`
+    pub theme: String,
+    /// Colors for dark terminals
+    pub dark: Palette,
+    /// Colors for light terminals
+    pub light: Palette,
+}
+
+impl Default for ColorConfig {
+    fn default() -> Self {
+        Self {
+            theme: "dark".to_string(),
+            dark: Palette::default(),
+            light: Palette {
+                error_output: ThemeColor(Color::Rgb(0xe4, 0x56, 0x49)),
+            },
+        }
+    }
+}
+
+impl ColorConfig {
+    /// Get the palette of the selected theme
+    pub fn palette(&self) -> &Palette {
+        match self.theme.as_str() {
+            "light" => &self.light,
+            _ => &self.dark,
+        }
+    }
+}
+
+/// Colors of one theme
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Palette {
+    /// Lines a command wrote to stderr
+    pub error_output: ThemeColor,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            error_output: ThemeColor(Color::Rgb(0xe0, 0x6c, 0x75)),
+        }
+    }
+}
+
+/// A color written as `#rrggbb`
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct ThemeColor(pub Color);
+
+impl TryFrom<String> for ThemeColor {
+    type Error = String;
+
+    fn try_from(color: String) -> Result<Self, Self::Error> {
+        let hex = color.strip_prefix('#')
+            .filter(|hex| hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+            .ok_or_else(|| format!("invalid color `{}`, expected #rrggbb", color))?;
+
+        let channel = |idx: usize| u8::from_str_radix(&hex[idx..idx + 2], 16).unwrap_or_default();
+        Ok(Self(Color::Rgb(channel(0), channel(2), channel(4))))
+    }
+}
+
+/// Configurable key bindings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct KeyBindings {
+    /// Quit mouse_term, asking first if a command is running
+    pub quit: KeyBinding,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            quit: KeyBinding { modifiers: KeyModifiers::CONTROL, code: KeyCode::Char('q') },
+        }
+    }
+}
+
+/// A key combination written like `ctrl+q`, `f2` or `esc`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct KeyBinding {
+    /// Modifier keys that must be held
+    pub modifiers: KeyModifiers,
+    /// The key itself
+    pub code: KeyCode,
+}
+
+impl KeyBinding {
+    /// Check whether a key event is this combination
+    pub fn matches(&self, key: &KeyEvent) -> bool {
+        key.code == self.code && key.modifiers.contains(self.modifiers)
+    }
+}
+
+impl TryFrom<String> for KeyBinding {
+    type Error = String;
+
+    fn try_from(binding: String) -> Result<Self, Self::Error> {
+        let mut parts: Vec<&str> = binding.split('+').collect();
+        let key = parts.pop().unwrap_or_default().to_lowercase();
+
+        let mut modifiers = KeyModifiers::NONE;
+        for part in parts {
+            modifiers |= match part.to_lowercase().as_str() {
+                "ctrl" => KeyModifiers::CONTROL,
+                "alt" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                other => return Err(format!("unknown modifier `{}` in key binding `{}`", other, binding)),
+            };
+        }
+
+        let code = match key.as_str() {
+            "enter" => KeyCode::Enter,
+            "esc" => KeyCode::Esc,
+            "tab" => KeyCode::Tab,
+            "backspace" => KeyCode::Backspace,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            other => {
+                let mut chars = other.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => KeyCode::Char(c),
+                    // Function keys such as `f2`
+                    _ => match other.strip_prefix('f').and_then(|n| n.parse().ok()) {
+                        Some(n) => KeyCode::F(n),
+                        None => return Err(format!("unknown key `{}` in key binding `{}`", other, binding)),
+                    },
+                }
+            }
+        };
+
+        Ok(Self { modifiers, code })
+    }
+}
+
+/// What runs the command lines typed in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// mouse_term parses and runs command lines itself
+    #[default]
+    Internal,
+    /// Command lines are handed to the user's shell with `-c`
+    Shell,
+}
+
+/// Settings that control how commands are executed
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ExecutionConfig {
+    /// What runs the command lines typed in
+    pub backend: Backend,
+    /// Shell used by the shell backend
+    ///
+    /// Falls back to `$SHELL` and then `/bin/sh` when unset.
+    pub shell: Option<String>,
+    /// Whether to run commands inside a pseudo-terminal
+    pub pty: bool,
+    /// Programs that take over the whole terminal, run with the UI suspended
+    pub fullscreen: Vec<String>,
+    /// Editor used to open files clicked in the file list
+    ///
+    /// Falls back to `$VISUAL`, `$EDITOR` and then `nano` when unset.
+    pub editor: Option<String>,
+    /// How long a command may run before it is stopped
+    pub timeout: Option<TimeLimit>,
+    /// Time limits for particular programs, overriding `timeout`
+    pub timeouts: HashMap<String, TimeLimit>,
+    /// Lines of output a command may produce before it is stopped, 0 or
+    /// unset for no limit
+    pub max_output_lines: Option<usize>,
+}
+
+impl Default for ExecutionConfig {
+    fn default() -> Self {
+        Self {
+            backend: Backend::Internal,
+            shell: None,
+            pty: true,
+            fullscreen: [
+                "vi", "vim", "nvim", "nano", "emacs", "less", "more", "man", "top", "htop", "btop", "tmux", "screen",
+            ]
+            .map(String::from)
+            .to_vec(),
+            editor: None,
+            timeout: None,
+            timeouts: HashMap::new(),
+            max_output_lines: None,
+        }
+    }
+}
+
+/// A time limit written like `30s`, `5m` or `1h`
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TimeLimit(pub Duration);
+
+impl TryFrom<String> for TimeLimit {
+    type Error = String;
+
+    fn try_from(limit: String) -> Result<Self, Self::Error> {
+        parse_duration(&limit)
+            .map(Self)
+            .ok_or_else(|| format!("invalid time limit `{}`, expected something like 30s, 5m or 1h", limit))
+    }
+}
+
+/// Parse a duration such as `90`, `1.5s`, `500ms`, `5m`, `2h` or `1d`
+///
+/// A number without a unit is in seconds, as for the `timeout` program.
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    let split = text.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number.parse().ok()?;
+
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 60.0 * 60.0,
+        "d" => number * 24.0 * 60.0 * 60.0,
+        _ => return None,
+    };
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+impl Config {
+    /// Load the configuration from `~/.mouse_term/config.toml`
+    ///
+    /// A `config.toml` in the current directory is never read, since it can
+    /// set aliases, functions and the shell that commands run with. A missing
+    /// file falls back to the defaults.
+    pub fn load_default() -> Result<Self> {
+        let Some(path) = Self::default_config_path() else {
+            return Ok(Self::default());
+        };
+        match fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Get the path of the rc file run at startup, `~/.mouse_term/rc`
+    pub fn rc_path() -> Option<PathBuf> {
+        dirs::home_dir().map(|home| home.join(".mouse_term").join("rc"))
+    }
+
+    /// Get the path of the config file, `~/.mouse_term/config.toml`
+    fn default_config_path() -> Option<PathBuf> {
+        dirs::home_dir().map(|home| home.join(".mouse_term").join("config.toml"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_key_binding() {
+        let binding = KeyBinding::try_from("ctrl+q".to_string()).unwrap();
+        assert_eq!(binding, KeyBinding { modifiers: KeyModifiers::CONTROL, code: KeyCode::Char('q') });
+        assert_eq!(KeyBinding::try_from("F2".to_string()).unwrap().code, KeyCode::F(2));
+        assert_eq!(KeyBinding::try_from("esc".to_string()).unwrap().code, KeyCode::Esc);
+        assert!(KeyBinding::try_from("hyper+q".to_string()).is_err());
+        assert!(KeyBinding::try_from("ctrl+nope".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_parse_colors() {
+        assert_eq!(ThemeColor::try_from("#e06c75".to_string()).unwrap(), ThemeColor(Color::Rgb(0xe0, 0x6c, 0x75)));
+        assert!(ThemeColor::try_from("red".to_string()).is_err());
+
+        // The bundled config.toml must stay loadable
+        let config: Config = toml::from_str(include_str!("../config.toml")).unwrap();
+        assert_eq!(config.colors.palette().error_output, ThemeColor(Color::Rgb(0xe0, 0x6c, 0x75)));
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1.5s"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_duration("5m"), Some(Duration::from_secs(300)));
+        assert_eq!(parse_duration("2h"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("10x"), None);
+        assert_eq!(parse_duration("s"), None);
+    }
+}
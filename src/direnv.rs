@@ -0,0 +1,244 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::expand::{self, ExpandContext};
+
+/// Files holding a directory's environment, in the order they are loaded
+const ENV_FILES: &[&str] = &[".env", ".envrc"];
+
+/// Which env files may be loaded, as answered when each was first seen
+///
+/// The answers are kept in `~/.mouse_term/trusted_env.json`, so each file
+/// is only asked about once. A trusted file is asked about again once its
+/// contents change, such as after a `git pull`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrustStore {
+    /// Files whose variables are loaded, with the SHA-256 of the contents
+    /// that were trusted
+    trusted_files: BTreeMap<PathBuf, String>,
+    /// Files that are left alone
+    denied: BTreeSet<PathBuf>,
+    /// Where the answers are saved, if anywhere
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl TrustStore {
+    /// Load the answers from the default location
+    pub fn load_default() -> Result<Self> {
+        let Some(path) = dirs::home_dir().map(|home| home.join(".mouse_term").join("trusted_env.json")) else {
+            return Ok(Self::default());
+        };
+
+        let mut store: Self = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => return Err(e.into()),
+        };
+        store.path = Some(path);
+        Ok(store)
+    }
+
+    /// Get whether a file with these contents is trusted, or `None` if it
+    /// hasn't been asked about or changed since it was trusted
+    pub fn decision(&self, file: &Path, contents: &str) -> Option<bool> {
+        if self.trusted_files.get(file).is_some_and(|hash| *hash == content_hash(contents)) {
+            Some(true)
+        } else if self.denied.contains(file) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    /// Check whether a file was trusted with other contents than it has now
+    pub fn is_changed(&self, file: &Path, contents: &str) -> bool {
+        self.trusted_files.get(file).is_some_and(|hash| *hash != content_hash(contents))
+    }
+
+    /// Remember whether a file with these contents is trusted
+    pub fn decide(&mut self, file: &Path, contents: &str, trusted: bool) -> Result<()> {
+        if trusted {
+            self.denied.remove(file);
+            self.trusted_files.insert(file.to_path_buf(), content_hash(contents));
+        } else {
+            self.trusted_files.remove(file);
+            self.denied.insert(file.to_path_buf());
+        }
+
+        if let Some(path) = &self.path {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, serde_json::to_string_pretty(self)?)?;
+        }
+        Ok(())
+    }
+}
+
+/// The env files of the nearest directory that has any
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnvFiles {
+    /// The directory holding the files
+    pub dir: PathBuf,
+    /// The files, in the order they are loaded
+    pub files: Vec<PathBuf>,
+}
+
+impl EnvFiles {
+    /// Find the env files that apply in a directory
+    ///
+    /// A project's files also apply in its subdirectories, so the directory
+    /// and then its parents are searched.
+    pub fn find(cwd: &Path) -> Option<Self> {
+        cwd.ancestors().find_map(|dir| {
+            let files: Vec<PathBuf> = ENV_FILES.iter()
+                .map(|name| dir.join(name))
+                .filter(|file| file.is_file())
+                .collect();
+            (!files.is_empty()).then(|| Self { dir: dir.to_path_buf(), files })
+        })
+    }
+}
+
+/// Variables loaded from a directory's env files, with the values they replaced
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirEnv {
+    /// The directory whose files were loaded
+    pub dir: PathBuf,
+    /// The files that were loaded
+    pub files: Vec<PathBuf>,
+    /// Each variable set, with its value from before, in the order they were set
+    saved: Vec<(String, Option<String>)>,
+}
+
+impl DirEnv {
+    /// Start loading the env files of a directory
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            files: Vec::new(),
+            saved: Vec::new(),
+        }
+    }
+
+    /// Load the variables of an env file into the session environment
+    ///
+    /// Returns how many lines were skipped because they don't set a
+    /// variable, such as the commands an `.envrc` may run.
+    pub fn load(&mut self, file: &Path, contents: &str, env: &mut HashMap<String, String>) -> usize {
+        let mut skipped = 0;
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((name, value)) = parse_assignment(line, env) else {
+                skipped += 1;
+                continue;
+            };
+
+            if !self.saved.iter().any(|(saved, _)| *saved == name) {
+                self.saved.push((name.clone(), env.get(&name).cloned()));
+            }
+            env.insert(name, value);
+        }
+
+        self.files.push(file.to_path_buf());
+        skipped
+    }
+
+    /// Get the names of the variables set, in the order they were set
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.saved.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Put back the values the loaded variables replaced
+    pub fn unload(self, env: &mut HashMap<String, String>) {
+        for (name, value) in self.saved.into_iter().rev() {
+            match value {
+                Some(value) => env.insert(name, value),
+                None => env.remove(&name),
+            };
+        }
+    }
+}
+
+/// Hash the contents of an env file, to notice when it changes
+fn content_hash(contents: &str) -> String {
+    Sha256::digest(contents.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Parse a `NAME=value` or `export NAME=value` line
+///
+/// The value is quoted and expanded like a word on the command line, so it
+/// can refer to variables set before it. An unquoted value ends at a ` #`
+/// comment.
+fn parse_assignment(line: &str, env: &HashMap<String, String>) -> Option<(String, String)> {
+    let line = line.strip_prefix("export ").map(str::trim_start).unwrap_or(line);
+    let (name, value) = line.split_once('=')?;
+    if !expand::is_valid_name(name) {
+        return None;
+    }
+
+    let value = if value.starts_with(['"', '\'']) {
+        value
+    } else {
+        value.split(" #").next().unwrap_or_default().trim_end()
+    };
+    let ctx = ExpandContext { env, last_exit_code: None };
+    Some((name.to_string(), expand::expand_path(value, &ctx)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_and_unload() {
+        let contents = "# database\nexport DB_HOST=localhost\nDB_URL=\"postgres://$DB_HOST/app\"\nRAW='$DB_HOST'\nPATH=./bin:$PATH # local tools\nlayout python3\n";
+        let mut env = HashMap::from([
+            ("PATH".to_string(), "/usr/bin".to_string()),
+            ("DB_HOST".to_string(), "db.internal".to_string()),
+        ]);
+        let before = env.clone();
+
+        let mut dir_env = DirEnv::new(Path::new("/project"));
+        assert_eq!(dir_env.load(Path::new("/project/.envrc"), contents, &mut env), 1);
+        assert_eq!(env["DB_HOST"], "localhost");
+        assert_eq!(env["DB_URL"], "postgres://localhost/app");
+        assert_eq!(env["RAW"], "$DB_HOST");
+        assert_eq!(env["PATH"], "./bin:/usr/bin");
+        assert_eq!(dir_env.names().collect::<Vec<_>>(), ["DB_HOST", "DB_URL", "RAW", "PATH"]);
+
+        // Leaving puts back what was there, including nothing
+        dir_env.unload(&mut env);
+        assert_eq!(env, before);
+    }
+
+    #[test]
+    fn test_trust_store() {
+        let mut store = TrustStore::default();
+        let file = Path::new("/project/.env");
+        let contents = "DB_HOST=localhost\n";
+        assert_eq!(store.decision(file, contents), None);
+        store.decide(file, contents, false).unwrap();
+        assert_eq!(store.decision(file, contents), Some(false));
+        store.decide(file, contents, true).unwrap();
+        assert_eq!(store.decision(file, contents), Some(true));
+
+        // Changed contents have to be trusted again
+        let changed = "DB_HOST=localhost\nPATH=./bin:$PATH\n";
+        assert_eq!(store.decision(file, changed), None);
+        assert!(store.is_changed(file, changed));
+        assert!(!store.is_changed(file, contents));
+
+        // Answers saved before contents were hashed are asked again
+        let old: TrustStore = serde_json::from_str(r#"{"trusted": ["/project/.env"], "denied": []}"#).unwrap();
+        assert_eq!(old.decision(file, contents), None);
+    }
+}
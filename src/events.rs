@@ -0,0 +1,272 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crossterm::event::{self, Event};
+
+/// How long the input thread waits for input before checking whether it
+/// should pause, where it can't wait for a wake-up along with the terminal
+#[cfg(not(unix))]
+const PAUSE_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Something the main loop wakes up for
+#[derive(Debug)]
+pub enum AppEvent {
+    /// A key, mouse action or resize from the terminal
+    Input(Event),
+    /// A job has new output or finished a pipeline
+    Output,
+    /// Reading from the terminal failed, so no more input will arrive
+    InputError(io::Error),
+}
+
+/// Wakes the main loop when a job has new output
+///
+/// One wake-up stands for everything sent until the main loop collects
+/// it, so a command printing fast doesn't flood the channel.
+#[derive(Debug, Clone, Default)]
+pub struct Notifier {
+    tx: Option<Sender<AppEvent>>,
+    pending: Arc<AtomicBool>,
+}
+
+impl Notifier {
+    /// Wake the main loop, unless a wake-up is already waiting
+    pub fn notify(&self) {
+        if let Some(tx) = &self.tx
+            && !self.pending.swap(true, Ordering::SeqCst)
+        {
+            let _ = tx.send(AppEvent::Output);
+        }
+    }
+}
+
+/// Whether the input thread should read, and whether it has stopped reading
+#[derive(Debug, Default)]
+struct Pause {
+    requested: bool,
+    stopped: bool,
+}
+
+/// Terminal input and job output, merged into one channel for the main loop
+///
+/// Input is read on its own thread, so the main loop sleeps until there is
+/// something to do instead of checking for output on a timer.
+pub struct Events {
+    rx: Receiver<AppEvent>,
+    notifier: Notifier,
+    pause: Arc<(Mutex<Pause>, Condvar)>,
+    waker: InputWaker,
+}
+
+impl Events {
+    /// Start reading terminal input
+    pub fn new() -> io::Result<Self> {
+        let (tx, rx) = mpsc::channel();
+        let pause: Arc<(Mutex<Pause>, Condvar)> = Arc::default();
+        let (waker, wait) = input_wait()?;
+
+        let input_tx = tx.clone();
+        let input_pause = Arc::clone(&pause);
+        thread::spawn(move || Self::read_input(input_tx, input_pause, wait));
+
+        Ok(Self {
+            rx,
+            notifier: Notifier {
+                tx: Some(tx),
+                pending: Arc::default(),
+            },
+            pause,
+            waker,
+        })
+    }
+
+    /// Get a notifier that wakes the main loop for job output
+    pub fn notifier(&self) -> Notifier {
+        self.notifier.clone()
+    }
+
+    /// Wait up to `timeout` for the next event
+    pub fn next(&self, timeout: Duration) -> Option<AppEvent> {
+        match self.rx.recv_timeout(timeout) {
+            Ok(AppEvent::Output) => {
+                // Output sent from now on needs a new wake-up
+                self.notifier.pending.store(false, Ordering::SeqCst);
+                Some(AppEvent::Output)
+            }
+            Ok(event) => Some(event),
+            Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => None,
+        }
+    }
+
+    /// Stop reading input, so a full-screen program gets every key
+    ///
+    /// Returns once the input thread has stopped.
+    pub fn pause(&self) {
+        let (lock, changed) = &*self.pause;
+        let mut pause = lock.lock().unwrap_or_else(|e| e.into_inner());
+        pause.requested = true;
+        self.waker.wake();
+        while !pause.stopped {
+            pause = changed.wait(pause).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Start reading input again after `pause`
+    pub fn resume(&self) {
+        let (lock, changed) = &*self.pause;
+        lock.lock().unwrap_or_else(|e| e.into_inner()).requested = false;
+        changed.notify_all();
+    }
+
+    /// Forward terminal input to the main loop until it goes away
+    fn read_input(tx: Sender<AppEvent>, pause: Arc<(Mutex<Pause>, Condvar)>, mut wait: InputWait) {
+        Self::forward_input(&tx, &pause, &mut wait);
+
+        // Nothing reads the terminal any more, so pausing needn't wait
+        let (lock, changed) = &*pause;
+        lock.lock().unwrap_or_else(|e| e.into_inner()).stopped = true;
+        changed.notify_all();
+    }
+
+    /// Send terminal input to the main loop, sitting out pauses, until reading fails
+    fn forward_input(tx: &Sender<AppEvent>, pause: &(Mutex<Pause>, Condvar), wait: &mut InputWait) {
+        let (lock, changed) = pause;
+        loop {
+            // Sit out a pause without touching the terminal
+            {
+                let mut pause = lock.lock().unwrap_or_else(|e| e.into_inner());
+                while pause.requested {
+                    pause.stopped = true;
+                    changed.notify_all();
+                    pause = changed.wait(pause).unwrap_or_else(|e| e.into_inner());
+                }
+                pause.stopped = false;
+            }
+
+            // Forward what has already arrived, then sleep until there is
+            // more or a pause is asked for
+            let event = match event::poll(Duration::ZERO) {
+                Ok(true) => event::read(),
+                Ok(false) => match wait.wait() {
+                    Ok(()) => continue,
+                    Err(e) => Err(e),
+                },
+                Err(e) => Err(e),
+            };
+            let sent = match event {
+                Ok(event) => tx.send(AppEvent::Input(event)),
+                Err(e) => {
+                    let _ = tx.send(AppEvent::InputError(e));
+                    return;
+                }
+            };
+            if sent.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Wakes the input thread while it waits for the terminal
+#[cfg(unix)]
+struct InputWaker(std::os::unix::net::UnixStream);
+
+/// Lets the input thread sleep until the terminal has input, the window is
+/// resized or it is woken
+#[cfg(unix)]
+struct InputWait {
+    /// The terminal crossterm reads, when it isn't stdin
+    tty: Option<std::fs::File>,
+    wake: std::os::unix::net::UnixStream,
+}
+
+/// Set up waiting for the terminal along with wake-ups
+///
+/// Resizes wake the input thread too, since crossterm only notices them
+/// when it is asked for input.
+#[cfg(unix)]
+fn input_wait() -> io::Result<(InputWaker, InputWait)> {
+    use std::io::IsTerminal;
+
+    let (waker, wake) = std::os::unix::net::UnixStream::pair()?;
+    waker.set_nonblocking(true)?;
+    wake.set_nonblocking(true)?;
+    signal_hook::low_level::pipe::register(libc::SIGWINCH, waker.try_clone()?)?;
+
+    // crossterm reads stdin when it is a terminal, and /dev/tty otherwise
+    let tty = if io::stdin().is_terminal() { None } else { Some(std::fs::File::open("/dev/tty")?) };
+    Ok((InputWaker(waker), InputWait { tty, wake }))
+}
+
+#[cfg(unix)]
+impl InputWaker {
+    /// Make the input thread's wait return
+    fn wake(&self) {
+        use std::io::Write;
+
+        // A full socket already has a wake-up waiting
+        let _ = (&self.0).write(&[0]);
+    }
+}
+
+#[cfg(unix)]
+impl InputWait {
+    /// Sleep until the terminal has input, the window is resized or a wake-up arrives
+    fn wait(&mut self) -> io::Result<()> {
+        use std::io::Read;
+        use std::os::fd::AsRawFd;
+
+        let tty = self.tty.as_ref().map_or(libc::STDIN_FILENO, |tty| tty.as_raw_fd());
+        let mut fds = [
+            libc::pollfd { fd: tty, events: libc::POLLIN, revents: 0 },
+            libc::pollfd { fd: self.wake.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+        ];
+        // SAFETY: fds is an array of two pollfds that outlives the call
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } == -1 {
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+        if fds[0].revents & (libc::POLLHUP | libc::POLLERR | libc::POLLNVAL) != 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the terminal was closed"));
+        }
+
+        // Take every wake-up, so the next wait sleeps again
+        let mut buf = [0; 64];
+        while matches!(self.wake.read(&mut buf), Ok(len) if len > 0) {}
+        Ok(())
+    }
+}
+
+/// Wakes the input thread while it waits for the terminal
+#[cfg(not(unix))]
+struct InputWaker;
+
+/// Lets the input thread wait for the terminal a little at a time
+#[cfg(not(unix))]
+struct InputWait;
+
+/// Set up waiting for the terminal
+#[cfg(not(unix))]
+fn input_wait() -> io::Result<(InputWaker, InputWait)> {
+    Ok((InputWaker, InputWait))
+}
+
+#[cfg(not(unix))]
+impl InputWaker {
+    /// The input thread notices a pause on its own within [`PAUSE_CHECK_INTERVAL`]
+    fn wake(&self) {}
+}
+
+#[cfg(not(unix))]
+impl InputWait {
+    /// Wait for input for at most [`PAUSE_CHECK_INTERVAL`]
+    fn wait(&mut self) -> io::Result<()> {
+        event::poll(PAUSE_CHECK_INTERVAL).map(drop)
+    }
+}
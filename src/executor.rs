@@ -1,434 +1,2330 @@
 use anyhow::Result;
-use std::io::{BufRead, BufReader, Write};
-use std::process::{Command, Stdio};
-use std::sync::mpsc::{self, Receiver, Sender};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver, SendError, Sender};
 use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::alias::Aliases;
+use crate::commands::{PathIndex, Suggestions};
+use crate::config::{self, Backend, ExecutionConfig};
+use crate::direnv::{DirEnv, EnvFiles, TrustStore};
+use crate::events::Notifier;
+use crate::expand::{self, ExpandContext};
+use crate::input;
+use crate::jobs::{Job, JobState, JobSummary, Script};
+use crate::output::LineBuffer;
+use crate::parser::{self, CommandList, Connector, ListItem, Pipeline, Redirect, SimpleCommand};
+use crate::process::{self, ResourceUsage, Signal};
+use crate::pty::{self, Pty};
+use crate::scrollback::Scrollback;
+use crate::shell::{ShellCapture, ShellState};
+use crate::sudo::{self, Askpass, AskpassOutcome, Secret, SudoPrompt};
+use crate::watch::{self, FileWatcher, Trigger, Watch};
+use crate::watchdog::{Limits, OutputCounter, Watchdog};
 
 /// Result of command execution
-#[derive(Debug, Clone)]
+#[derive(Debug, Default)]
 pub struct ExecutionResult {
     /// Exit code of the command
     pub exit_code: Option<i32>,
-    /// Standard output lines
-    pub stdout: Vec<String>,
-    /// Standard error lines
-    pub stderr: Vec<String>,
+    /// Signal that killed the command, when there is no exit code
+    pub signal: Option<i32>,
+    /// Time and resources the command used, for external commands
+    pub usage: Option<ResourceUsage>,
+    /// Output lines from both streams, in the order they arrived
+    pub output: Scrollback,
+    /// Index of the unfinished line of each stream in `output`
+    open_lines: [Option<usize>; 2],
+}
+
+impl ExecutionResult {
+    /// Add a line read from a command
+    ///
+    /// An unfinished line, such as a progress bar, replaces the previous
+    /// unfinished line of the same stream until it is complete.
+    pub fn push_line(&mut self, line: OutputLine, complete: bool) {
+        let open = &mut self.open_lines[line.stream as usize];
+        match open.and_then(|idx| self.output.get_mut(idx)) {
+            Some(open_line) => *open_line = line,
+            None => {
+                self.output.push(line);
+                *open = Some(self.output.len() - 1);
+            }
+        }
+
+        if complete {
+            *open = None;
+        }
+    }
+
+    /// Describe how the command ended, with the time and resources it used
+    pub fn summary(&self) -> String {
+        let status = match (self.exit_code, self.signal) {
+            (Some(code), _) => format!("exit {}", code),
+            (None, Some(signal)) => format!("killed by {}", process::signal_name(signal)),
+            (None, None) => "terminated".to_string(),
+        };
+
+        match self.usage {
+            Some(usage) => format!(
+                "{} · {} · user {} · sys {} · max RSS {}",
+                status,
+                format_duration(usage.wall),
+                format_duration(usage.user),
+                format_duration(usage.system),
+                format_size(usage.max_rss_kb),
+            ),
+            None => status,
+        }
+    }
+
+    /// Add a line to the output as if the command wrote it to stdout
+    pub fn push_stdout(&mut self, text: impl Into<String>) {
+        self.output.push(OutputLine::new(Stream::Stdout, text));
+    }
+
+    /// Add a line to the output as if the command wrote it to stderr
+    pub fn push_stderr(&mut self, text: impl Into<String>) {
+        self.output.push(OutputLine::new(Stream::Stderr, text));
+    }
+}
+
+/// Format a duration for a summary line, such as `0.42s` or `3m07s`
+///
+/// The unit is picked after rounding, so 59.996s shows as `1m00s`.
+fn format_duration(duration: std::time::Duration) -> String {
+    let centis = (duration.as_secs_f64() * 100.0).round() as u64;
+    if centis < 60 * 100 {
+        format!("{}.{:02}s", centis / 100, centis % 100)
+    } else {
+        let secs = duration.as_secs_f64().round() as u64;
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    }
+}
+
+/// Format a size in kilobytes, such as `812 KB` or `45.2 MB`
+///
+/// As with durations, the unit is picked after rounding.
+fn format_size(kb: u64) -> String {
+    let mb = kb as f64 / 1024.0;
+    if kb < 1024 {
+        format!("{} KB", kb)
+    } else if (mb * 10.0).round() < 1024.0 * 10.0 {
+        format!("{:.1} MB", mb)
+    } else {
+        format!("{:.1} GB", mb / 1024.0)
+    }
+}
+
+/// Check whether a pipeline stage runs sudo
+fn is_sudo_stage(command: &SimpleCommand) -> bool {
+    command.words.first().is_some_and(|word| word == "sudo")
+}
+
+/// How a pipeline ended
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Completion {
+    /// Exit code, `None` when the pipeline was killed by a signal
+    pub exit_code: Option<i32>,
+    /// Signal that killed the last stage
+    pub signal: Option<i32>,
+    /// Time and resources every stage used together, if measured
+    pub usage: Option<ResourceUsage>,
 }
 
-impl Default for ExecutionResult {
-    fn default() -> Self {
-        Self {
-            exit_code: None,
-            stdout: Vec::new(),
-            stderr: Vec::new(),
+impl Completion {
+    /// A pipeline that exited with a code and was not measured, like a built-in
+    pub fn exited(code: i32) -> Self {
+        Self {
+            exit_code: Some(code),
+            ..Self::default()
+        }
+    }
+}
+
+/// The stream a line of output was written to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stream {
+    /// Standard output
+    Stdout,
+    /// Standard error
+    Stderr,
+}
+
+/// A line of output and when it arrived
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputLine {
+    /// The stream the line came from
+    pub stream: Stream,
+    /// The line without its terminator
+    pub text: String,
+    /// When the line was read
+    pub time: SystemTime,
+}
+
+impl OutputLine {
+    /// Create a line read just now
+    pub fn new(stream: Stream, text: impl Into<String>) -> Self {
+        Self {
+            stream,
+            text: text.into(),
+            time: SystemTime::now(),
+        }
+    }
+}
+
+/// Destination for a pipeline stage's stdout or stderr
+enum Sink {
+    /// A file or the terminal side of a pty
+    File(File),
+    /// The write end of a pipe
+    Pipe(io::PipeWriter),
+}
+
+impl Sink {
+    /// Duplicate the sink so two streams can share it
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(match self {
+            Sink::File(file) => Sink::File(file.try_clone()?),
+            Sink::Pipe(pipe) => Sink::Pipe(pipe.try_clone()?),
+        })
+    }
+}
+
+impl From<Sink> for Stdio {
+    fn from(sink: Sink) -> Self {
+        match sink {
+            Sink::File(file) => file.into(),
+            Sink::Pipe(pipe) => pipe.into(),
+        }
+    }
+}
+
+/// Commands the executor runs itself
+const BUILTINS: &[&str] = &[
+    "cd", "pushd", "popd", "dirs", "alias", "unalias", "export", "unset", "env",
+    "jobs", "fg", "bg", "kill", "source", ".", "timeout", "which", "type", "watch",
+];
+
+/// Built-in commands that still run here with the shell backend, since
+/// they work on jobs and directories the shell doesn't know about
+const SESSION_BUILTINS: &[&str] = &["pushd", "popd", "dirs", "jobs", "fg", "bg", "watch"];
+
+/// Most files `source` reads for one command line, so a file that
+/// sources itself stops
+const MAX_SOURCED_FILES: usize = 64;
+
+/// How often the stages of a pipeline are checked for exiting where they
+/// can't be waited on without reaping them
+const EXIT_POLL: Duration = Duration::from_millis(50);
+
+/// Command executor
+#[derive(Default)]
+pub struct Executor {
+    /// Execution settings
+    config: ExecutionConfig,
+    /// Size of the output pane (rows, columns) used for new ptys
+    pty_size: (u16, u16),
+    /// Environment variables passed to every command of the session
+    env: HashMap<String, String>,
+    /// Exit code of the last finished foreground pipeline, for `$?`
+    last_exit_code: Option<i32>,
+    /// Summary of the last finished external foreground pipeline
+    last_summary: Option<String>,
+    /// The job typed input goes to
+    foreground: Job,
+    /// Jobs running in the background or stopped, by job number
+    jobs: BTreeMap<usize, Job>,
+    /// Background job shown in the output pane instead of the foreground job
+    selected: Option<usize>,
+    /// Job `fg` asked to bring to the foreground
+    resume: Option<usize>,
+    /// Directories saved by `pushd`, most recent first, not counting the
+    /// current directory
+    dir_stack: Vec<PathBuf>,
+    /// Aliases and functions expanded before a command line is parsed
+    aliases: Aliases,
+    /// Executables on PATH, for finding commands and suggesting corrections
+    path_index: PathIndex,
+    /// How often each command was typed, to rank suggestions
+    command_usage: HashMap<String, usize>,
+    /// Corrections for a foreground command that wasn't found
+    suggestions: Option<Suggestions>,
+    /// Wakes the main loop when a job has output
+    notifier: Notifier,
+    /// A sudo command whose password has to be asked for again
+    sudo_prompt: Option<SudoPrompt>,
+    /// Which `.env` and `.envrc` files may be loaded
+    env_trust: TrustStore,
+    /// Variables loaded from the env files of the current directory
+    dir_env: Option<DirEnv>,
+    /// An env file to ask whether to trust
+    env_trust_prompt: Option<PathBuf>,
+    /// The command line typed last, for `watch` on its own
+    last_command: Option<String>,
+    /// A command line `watch` runs again and again
+    watch: Option<Watch>,
+}
+
+/// What a command name runs, as reported by `which` and `type`
+enum CommandKind {
+    /// An alias for a command line
+    Alias(String),
+    /// A function running a command line
+    Function(String),
+    /// A command the executor runs itself
+    Builtin,
+    /// A program, found on PATH or given as a path
+    Program(PathBuf),
+}
+
+/// Type of output from command execution
+#[derive(Debug, Clone)]
+pub enum ExecutionOutput {
+    /// A complete line of output from either stream
+    Line(OutputLine),
+    /// The current state of a line that is still being written
+    Partial(OutputLine),
+    /// Command finished
+    Finished(Completion),
+}
+
+impl ExecutionOutput {
+    /// A line of output read just now
+    fn line(stream: Stream, text: impl Into<String>) -> Self {
+        ExecutionOutput::Line(OutputLine::new(stream, text))
+    }
+}
+
+/// Sends a pipeline's output to its job, waking the main loop to collect it
+#[derive(Debug, Clone)]
+struct OutputSender {
+    tx: Sender<ExecutionOutput>,
+    notifier: Notifier,
+}
+
+impl OutputSender {
+    /// Send output, failing once the job no longer listens
+    fn send(&self, output: ExecutionOutput) -> Result<(), SendError<ExecutionOutput>> {
+        self.tx.send(output)?;
+        self.notifier.notify();
+        Ok(())
+    }
+}
+
+/// Something the thread running a pipeline wakes up for
+#[derive(Debug)]
+pub enum PipelineEvent {
+    /// A signal for every stage still running
+    Signal(Signal),
+    /// A stage exited, or waiting for it failed, and is ready to be reaped
+    Exited,
+    /// The pipeline has output more lines than allowed
+    OutputLimit,
+}
+
+impl Executor {
+    /// Create a new command executor with the default settings
+    #[cfg(test)]
+    pub fn new() -> Self {
+        Self::with_config(ExecutionConfig::default())
+    }
+
+    /// Create a new command executor with custom settings
+    pub fn with_config(config: ExecutionConfig) -> Self {
+        Self {
+            config,
+            // The session starts out with the environment mouse_term was launched with
+            env: std::env::vars().collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Start the session with aliases and functions, such as those from the config
+    pub fn with_aliases(mut self, aliases: Aliases) -> Self {
+        self.aliases = aliases;
+        self
+    }
+
+    /// Wake the main loop through `notifier` whenever a job has output
+    pub fn with_notifier(mut self, notifier: Notifier) -> Self {
+        self.notifier = notifier;
+        self
+    }
+
+    /// Load `.env` and `.envrc` files according to `env_trust`
+    pub fn with_env_trust(mut self, env_trust: TrustStore) -> Self {
+        self.env_trust = env_trust;
+        self
+    }
+
+    /// Resize the terminal commands run in to match the output pane
+    pub fn resize(&mut self, rows: u16, cols: u16) {
+        self.pty_size = (rows, cols);
+
+        // Let running full-screen programs redraw at the new size
+        for job in std::iter::once(&self.foreground).chain(self.jobs.values()) {
+            if let Some(master) = &job.pty_master {
+                let _ = pty::resize(master, rows, cols);
+            }
+        }
+    }
+
+    /// Allocate a pty for the next command if pty mode is enabled
+    ///
+    /// Returns `None` when pty mode is off or the pty could not be allocated,
+    /// in which case the command falls back to plain pipes.
+    fn open_pty(&self) -> Option<Pty> {
+        if !self.config.pty {
+            return None;
+        }
+
+        let (rows, cols) = self.pty_size;
+        Pty::open(rows, cols).ok()
+    }
+
+    /// Handle the cd command, returning its exit code
+    fn handle_cd_command(&mut self, args: &[String], out: &mut ExecutionResult) -> Result<i32> {
+        // Get the target directory
+        let target_dir = match args.first().map(String::as_str) {
+            // If no arguments, cd to home directory
+            None => self.env.get("HOME")
+                .map(PathBuf::from)
+                .or_else(dirs::home_dir)
+                .ok_or_else(|| anyhow::anyhow!("Could not determine home directory"))?,
+            // `cd -` goes back to the previous directory
+            Some("-") => self.env.get("OLDPWD")
+                .map(PathBuf::from)
+                .ok_or_else(|| anyhow::anyhow!("cd: OLDPWD not set"))?,
+            Some(dir) => self.resolve_cdpath(dir),
+        };
+
+        // Change the current directory
+        match self.change_dir(&target_dir) {
+            Ok(current_dir) => {
+                // Success - add current directory to output
+                out.push_stdout(format!("Changed directory to: {}", current_dir.display()));
+
+                // Automatically run ls after changing directory
+                if let Ok(ls_output) = self.run_command_sync("ls", &[]) {
+                    // Add ls output to the result
+                    out.output.extend(ls_output.output.lines(0..ls_output.output.len()));
+                }
+
+                self.update_dir_env(out);
+                Ok(0)
+            },
+            Err(e) => {
+                // Error - add error message to output
+                out.push_stderr(format!("Failed to change directory: {}", e));
+                Ok(1)
+            }
+        }
+    }
+
+    /// Handle the pushd command, returning its exit code
+    ///
+    /// `pushd dir` saves the current directory on the stack and changes to
+    /// `dir`, `pushd +n` rotates the nth directory to the top, and `pushd`
+    /// on its own swaps the top two.
+    fn handle_pushd_command(&mut self, args: &[String], out: &mut ExecutionResult) -> Result<i32> {
+        let mut stack = self.full_dir_stack()?;
+        match args.first() {
+            None if stack.len() < 2 => anyhow::bail!("pushd: no other directory"),
+            None => stack.swap(0, 1),
+            Some(arg) => match self.stack_index(arg) {
+                Some(idx) => stack.rotate_left(idx?),
+                None => stack.insert(0, self.resolve_cdpath(arg)),
+            },
+        }
+
+        self.change_dir(&stack[0])?;
+        self.dir_stack = stack.split_off(1);
+        self.push_dir_stack(out, false)?;
+        self.update_dir_env(out);
+        Ok(0)
+    }
+
+    /// Handle the popd command, returning its exit code
+    ///
+    /// `popd` changes to the directory on top of the stack and removes it,
+    /// while `popd +n` only removes the nth directory.
+    fn handle_popd_command(&mut self, args: &[String], out: &mut ExecutionResult) -> Result<i32> {
+        let mut stack = self.full_dir_stack()?;
+        if stack.len() < 2 {
+            anyhow::bail!("popd: directory stack empty");
+        }
+
+        let idx = match args.first() {
+            None => 0,
+            Some(arg) => self.stack_index(arg)
+                .unwrap_or_else(|| Err(anyhow::anyhow!("popd: {}: invalid argument", arg)))?,
+        };
+        stack.remove(idx);
+
+        if idx == 0 {
+            self.change_dir(&stack[0])?;
+        }
+        self.dir_stack = stack.split_off(1);
+        self.push_dir_stack(out, false)?;
+        self.update_dir_env(out);
+        Ok(0)
+    }
+
+    /// Handle the dirs command, returning its exit code
+    ///
+    /// `-v` numbers the directories and `-c` clears the stack.
+    fn handle_dirs_command(&mut self, args: &[String], out: &mut ExecutionResult) -> Result<i32> {
+        match args.first().map(String::as_str) {
+            None => self.push_dir_stack(out, false)?,
+            Some("-v") => self.push_dir_stack(out, true)?,
+            Some("-c") => self.dir_stack.clear(),
+            Some(arg) => anyhow::bail!("dirs: {}: invalid option", arg),
+        }
+        Ok(0)
+    }
+
+    /// Make a directory the current one, keeping `$PWD` and `$OLDPWD` up to date
+    fn change_dir(&mut self, dir: &Path) -> Result<PathBuf> {
+        let previous = std::env::current_dir().ok();
+        std::env::set_current_dir(dir).map_err(|e| anyhow::anyhow!("{}: {}", dir.display(), e))?;
+
+        let current_dir = std::env::current_dir()?;
+        if let Some(previous) = previous {
+            self.env.insert("OLDPWD".to_string(), previous.to_string_lossy().to_string());
+        }
+        self.env.insert("PWD".to_string(), current_dir.to_string_lossy().to_string());
+        Ok(current_dir)
+    }
+
+    /// Load the env files that apply in the current directory, unloading
+    /// those of the directory left
+    ///
+    /// A file that hasn't been trusted or turned down yet is handed to the
+    /// UI to ask about, and nothing of its directory is loaded until then.
+    fn update_dir_env(&mut self, out: &mut ExecutionResult) {
+        let found = std::env::current_dir().ok().and_then(|cwd| EnvFiles::find(&cwd));
+        if self.dir_env.as_ref().map(|dir_env| &dir_env.dir) == found.as_ref().map(|found| &found.dir) {
+            return;
+        }
+
+        if let Some(dir_env) = self.dir_env.take() {
+            out.push_stdout(format!("direnv: unloading {}", Self::describe_env_files(&dir_env.files)));
+            dir_env.unload(&mut self.env);
+        }
+        let Some(found) = found else {
+            return;
+        };
+
+        // Trust goes with the contents, so each file is read once for both
+        let mut files = Vec::new();
+        for file in &found.files {
+            match fs::read_to_string(file) {
+                Ok(contents) => files.push((file, contents)),
+                Err(e) => out.push_stderr(format!("direnv: {}: {}", file.display(), e)),
+            }
+        }
+
+        if let Some((file, contents)) = files.iter().find(|(file, contents)| self.env_trust.decision(file, contents).is_none()) {
+            if self.env_trust.is_changed(file, contents) {
+                out.push_stderr(format!("direnv: {} changed since it was trusted", file.display()));
+            }
+            self.env_trust_prompt = Some(file.to_path_buf());
+            return;
+        }
+
+        let mut dir_env = DirEnv::new(&found.dir);
+        for (file, contents) in files.iter().filter(|(file, contents)| self.env_trust.decision(file, contents) == Some(true)) {
+            let skipped = dir_env.load(file, contents, &mut self.env);
+            if skipped > 0 {
+                out.push_stderr(format!("direnv: {}: skipped {} lines that don't set a variable", file.display(), skipped));
+            }
+        }
+
+        if !dir_env.files.is_empty() {
+            let names: Vec<&str> = dir_env.names().collect();
+            out.push_stdout(format!("direnv: loading {} ({})", Self::describe_env_files(&dir_env.files), names.join(" ")));
+            self.dir_env = Some(dir_env);
+        }
+    }
+
+    /// List env files by path, for messages
+    fn describe_env_files(files: &[PathBuf]) -> String {
+        files.iter().map(|file| file.display().to_string()).collect::<Vec<_>>().join(", ")
+    }
+
+    /// Load the env files of the current directory into the foreground output
+    ///
+    /// Used for the directory mouse_term starts in, which isn't entered with `cd`.
+    pub fn load_dir_env(&mut self) {
+        let mut result = std::mem::take(&mut self.foreground.result);
+        self.update_dir_env(&mut result);
+        self.foreground.result = result;
+    }
+
+    /// Remember whether an env file, as it is now, is trusted, loading it
+    /// if it applies here
+    pub fn trust_env_file(&mut self, file: &Path, trusted: bool) {
+        let decided = fs::read_to_string(file)
+            .map_err(anyhow::Error::from)
+            .and_then(|contents| self.env_trust.decide(file, &contents, trusted));
+        if let Err(e) = decided {
+            self.foreground.result.push_stderr(format!("direnv: failed to remember the answer: {}", e));
+        }
+        self.load_dir_env();
+    }
+
+    /// Take an env file the UI should ask whether to trust
+    pub fn take_env_trust_prompt(&mut self) -> Option<PathBuf> {
+        self.env_trust_prompt.take()
+    }
+
+    /// Get the variables loaded from the current directory's env files
+    pub fn dir_env(&self) -> Option<&DirEnv> {
+        self.dir_env.as_ref()
+    }
+
+    /// Find a relative directory in `$CDPATH`, like a shell's `cd`
+    ///
+    /// Paths starting with `/`, `.` or `..` are used as they are, as is a
+    /// directory CDPATH has no match for.
+    fn resolve_cdpath(&self, dir: &str) -> PathBuf {
+        let path = Path::new(dir);
+        let explicit = matches!(
+            path.components().next(),
+            Some(Component::RootDir | Component::CurDir | Component::ParentDir)
+        );
+
+        if !explicit && let Some(cdpath) = self.env.get("CDPATH") {
+            // An empty entry stands for the current directory
+            let found = cdpath.split(':')
+                .map(|base| Path::new(base).join(path))
+                .find(|candidate| candidate.is_dir());
+            if let Some(found) = found {
+                return found;
+            }
+        }
+
+        path.to_path_buf()
+    }
+
+    /// Get the directory stack with the current directory on top
+    fn full_dir_stack(&self) -> Result<Vec<PathBuf>> {
+        let mut stack = vec![std::env::current_dir()?];
+        stack.extend(self.dir_stack.iter().cloned());
+        Ok(stack)
+    }
+
+    /// Turn a `+n` or `-n` argument into a position in the directory stack
+    ///
+    /// `+n` counts from the current directory at 0, and `-n` from the
+    /// bottom. Returns `None` if the argument is not a position.
+    fn stack_index(&self, arg: &str) -> Option<Result<usize>> {
+        let (from_bottom, n) = match arg.split_at_checked(1)? {
+            ("+", n) => (false, n),
+            ("-", n) => (true, n),
+            _ => return None,
+        };
+        let n: usize = n.parse().ok()?;
+
+        let len = self.dir_stack.len() + 1;
+        if n >= len {
+            return Some(Err(anyhow::anyhow!("{}: directory stack index out of range", arg)));
+        }
+        Some(Ok(if from_bottom { len - 1 - n } else { n }))
+    }
+
+    /// Show the directory stack, current directory first, like `dirs`
+    fn push_dir_stack(&self, out: &mut ExecutionResult, numbered: bool) -> Result<()> {
+        let home = self.env.get("HOME").map(PathBuf::from);
+        let dirs = self.full_dir_stack()?
+            .into_iter()
+            .map(|dir| expand::collapse_home(&dir, home.as_deref()));
+
+        if numbered {
+            for (idx, dir) in dirs.enumerate() {
+                out.push_stdout(format!("{:>2}  {}", idx, dir));
+            }
+        } else {
+            out.push_stdout(dirs.collect::<Vec<_>>().join(" "));
+        }
+        Ok(())
+    }
+
+    /// Handle the alias command, returning its exit code
+    ///
+    /// `alias name=value` defines an alias for the session, `alias name`
+    /// shows one and `alias` on its own lists them all.
+    fn handle_alias_command(&mut self, args: &[String], out: &mut ExecutionResult) -> i32 {
+        if args.is_empty() {
+            for (name, value) in &self.aliases.aliases {
+                out.push_stdout(format!("alias {}={}", name, input::quote_literal(value)));
+            }
+            return 0;
+        }
+
+        let mut exit_code = 0;
+        for arg in args {
+            match arg.split_once('=') {
+                Some((name, value)) => {
+                    self.aliases.aliases.insert(name.to_string(), value.to_string());
+                }
+                None => match self.aliases.aliases.get(arg) {
+                    Some(value) => out.push_stdout(format!("alias {}={}", arg, input::quote_literal(value))),
+                    None => {
+                        out.push_stderr(format!("alias: {}: not found", arg));
+                        exit_code = 1;
+                    }
+                },
+            }
+        }
+        exit_code
+    }
+
+    /// Handle the unalias command, returning its exit code
+    ///
+    /// `unalias -a` removes every alias.
+    fn handle_unalias_command(&mut self, args: &[String], out: &mut ExecutionResult) -> i32 {
+        let mut exit_code = 0;
+        for arg in args {
+            if arg == "-a" {
+                self.aliases.aliases.clear();
+            } else if self.aliases.aliases.remove(arg).is_none() {
+                out.push_stderr(format!("unalias: {}: not found", arg));
+                exit_code = 1;
+            }
+        }
+        exit_code
+    }
+
+    /// Handle the which command, returning its exit code
+    ///
+    /// Shows what runs for each name: an alias, function, builtin, or the
+    /// program on PATH.
+    fn handle_which_command(&mut self, args: &[String], out: &mut ExecutionResult) -> i32 {
+        let mut exit_code = 0;
+        for name in args {
+            match self.describe_command(name) {
+                Some(CommandKind::Alias(value)) => out.push_stdout(format!("{}: aliased to {}", name, value)),
+                Some(CommandKind::Function(body)) => out.push_stdout(format!("{}: function running {}", name, body)),
+                Some(CommandKind::Builtin) => out.push_stdout(format!("{}: shell built-in command", name)),
+                Some(CommandKind::Program(path)) => out.push_stdout(path.display().to_string()),
+                None => {
+                    out.push_stderr(format!("{} not found", name));
+                    exit_code = 1;
+                }
+            }
+        }
+        exit_code
+    }
+
+    /// Handle the type command, returning its exit code
+    fn handle_type_command(&mut self, args: &[String], out: &mut ExecutionResult) -> i32 {
+        let mut exit_code = 0;
+        for name in args {
+            match self.describe_command(name) {
+                Some(CommandKind::Alias(value)) => out.push_stdout(format!("{} is aliased to `{}'", name, value)),
+                Some(CommandKind::Function(body)) => out.push_stdout(format!("{} is a function running `{}'", name, body)),
+                Some(CommandKind::Builtin) => out.push_stdout(format!("{} is a shell builtin", name)),
+                Some(CommandKind::Program(path)) => out.push_stdout(format!("{} is {}", name, path.display())),
+                None => {
+                    out.push_stderr(format!("type: {}: not found", name));
+                    exit_code = 1;
+                }
+            }
+        }
+        exit_code
+    }
+
+    /// Work out what runs for a command name, in the order they are tried
+    fn describe_command(&mut self, name: &str) -> Option<CommandKind> {
+        if let Some(value) = self.aliases.aliases.get(name) {
+            Some(CommandKind::Alias(value.clone()))
+        } else if let Some(body) = self.aliases.functions.get(name) {
+            Some(CommandKind::Function(body.clone()))
+        } else if BUILTINS.contains(&name) {
+            Some(CommandKind::Builtin)
+        } else if name.contains('/') {
+            Some(CommandKind::Program(PathBuf::from(name))).filter(|_| Path::new(name).is_file())
+        } else {
+            let path = self.env.get("PATH").cloned().unwrap_or_default();
+            self.path_index.lookup(&path, name).map(CommandKind::Program)
+        }
+    }
+
+    /// Find the first stage of a pipeline whose program isn't on PATH
+    ///
+    /// Programs given as a path are left for spawning to report.
+    fn missing_command(&mut self, pipeline: &Pipeline) -> Option<String> {
+        let path = self.env.get("PATH").cloned().unwrap_or_default();
+        pipeline.commands.iter()
+            .map(|command| &command.words[0])
+            .find(|program| !program.contains('/') && self.path_index.lookup(&path, program).is_none())
+            .cloned()
+    }
+
+    /// Report a command that wasn't found, suggesting known commands close to it
+    fn report_missing_command(&mut self, job: &mut Job, missing: &str) {
+        job.result.push_stderr(format!("{}: command not found", missing));
+
+        let path = self.env.get("PATH").cloned().unwrap_or_default();
+        let names: Vec<&str> = self.path_index.names(&path)
+            .chain(BUILTINS.iter().copied())
+            .chain(self.aliases.aliases.keys().map(String::as_str))
+            .chain(self.aliases.functions.keys().map(String::as_str))
+            .collect();
+        let suggestions = Suggestions::new(&job.command, missing, names, &self.command_usage);
+
+        if suggestions.candidates.is_empty() {
+            return;
+        }
+        job.result.push_stderr(format!("Did you mean: {}?", suggestions.candidates.join(", ")));
+        if !job.background {
+            self.suggestions = Some(suggestions);
+        }
+    }
+
+    /// Count a typed command line's command, so suggestions favour familiar ones
+    pub fn remember_command(&mut self, line: &str) {
+        if let Some(program) = line.split_whitespace().next()
+            && !program.contains('/')
+        {
+            *self.command_usage.entry(program.to_string()).or_default() += 1;
+        }
+    }
+
+    /// Handle `sudo -k` and `sudo -K`, which only clear cached credentials
+    ///
+    /// sudo keeps credentials per terminal, so they run on mouse_term's own
+    /// terminal like every sudo pipeline.
+    fn handle_sudo_command(&self, flag: &str, out: &mut ExecutionResult) -> i32 {
+        if sudo::run_quietly(&self.env, &[flag]) {
+            out.push_stdout("sudo: cached credentials cleared");
+            0
+        } else {
+            out.push_stderr("sudo: failed to clear cached credentials");
+            1
+        }
+    }
+
+    /// Handle the export command, returning its exit code
+    fn handle_export_command(&mut self, args: &[String], out: &mut ExecutionResult) -> i32 {
+        // Without arguments, list the environment like `export -p`, quoted
+        // so each line can be pasted back into a shell
+        if args.is_empty() {
+            for (name, value) in self.sorted_env() {
+                out.push_stdout(format!("export {}={}", name, input::quote_literal(&value)));
+            }
+            return 0;
+        }
+
+        let mut exit_code = 0;
+        for arg in args {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg.as_str(), None),
+            };
+
+            if !expand::is_valid_name(name) {
+                out.push_stderr(format!("export: `{}': not a valid identifier", arg));
+                exit_code = 1;
+                continue;
+            }
+
+            // `export NAME` keeps the current value, or defines it as empty
+            let value = value.map(str::to_string)
+                .unwrap_or_else(|| self.env.get(name).cloned().unwrap_or_default());
+            self.env.insert(name.to_string(), value);
+        }
+
+        exit_code
+    }
+
+    /// Handle the unset command, returning its exit code
+    fn handle_unset_command(&mut self, args: &[String]) -> i32 {
+        for name in args {
+            self.env.remove(name);
+        }
+        0
+    }
+
+    /// Handle the env command, returning its exit code
+    fn handle_env_command(&self, out: &mut ExecutionResult) -> i32 {
+        for (name, value) in self.sorted_env() {
+            out.push_stdout(format!("{}={}", name, value));
+        }
+        0
+    }
+
+    /// Handle the jobs command, returning its exit code
+    fn handle_jobs_command(&self, out: &mut ExecutionResult) -> i32 {
+        for (number, job) in &self.jobs {
+            out.push_stdout(format!("[{}]  {:<10} {}", number, job.state().to_string(), job.command));
+        }
+        0
+    }
+
+    /// Handle the fg command, returning its exit code
+    ///
+    /// The job is swapped in once the command list running `fg` is done.
+    fn handle_fg_command(&mut self, args: &[String], out: &mut ExecutionResult) -> i32 {
+        match self.parse_job_spec(args.first()) {
+            Ok(number) => {
+                self.resume = Some(number);
+                0
+            }
+            Err(e) => {
+                out.push_stderr(format!("fg: {}", e));
+                1
+            }
+        }
+    }
+
+    /// Handle the bg command, returning its exit code
+    fn handle_bg_command(&mut self, args: &[String], out: &mut ExecutionResult) -> i32 {
+        let number = match self.parse_job_spec(args.first()) {
+            Ok(number) => number,
+            Err(e) => {
+                out.push_stderr(format!("bg: {}", e));
+                return 1;
+            }
+        };
+
+        let job = self.jobs.get_mut(&number).expect("job spec was resolved");
+        if job.stopped {
+            job.signal(Signal::Continue);
+        }
+        out.push_stdout(format!("[{}] {} &", number, job.command));
+        0
+    }
+
+    /// Handle kill for job specs such as `kill -TERM %1`, returning its exit code
+    ///
+    /// Plain pids given alongside job specs are signalled directly.
+    fn handle_kill_command(&mut self, args: &[String], out: &mut ExecutionResult) -> i32 {
+        let (signal, targets) = match args.split_first() {
+            Some((option, targets)) if option.starts_with('-') => match Signal::from_name(&option[1..]) {
+                Some(signal) => (signal, targets),
+                None => {
+                    out.push_stderr(format!("kill: {}: invalid signal specification", &option[1..]));
+                    return 1;
+                }
+            },
+            _ => (Signal::Terminate, args),
+        };
+
+        let mut exit_code = 0;
+        for target in targets {
+            if !target.starts_with('%') {
+                let result = match target.parse() {
+                    Ok(pid) => process::signal_pid(pid, signal).map_err(|e| format!("({}) - {}", pid, e)),
+                    Err(_) => Err(format!("{}: arguments must be process or job IDs", target)),
+                };
+                if let Err(e) = result {
+                    out.push_stderr(format!("kill: {}", e));
+                    exit_code = 1;
+                }
+                continue;
+            }
+
+            let job = match self.parse_job_spec(Some(target)) {
+                Ok(number) => self.jobs.get_mut(&number).expect("job spec was resolved"),
+                Err(e) => {
+                    out.push_stderr(format!("kill: {}", e));
+                    exit_code = 1;
+                    continue;
+                }
+            };
+
+            job.signal(signal);
+            // A stopped job only acts on the signal once it runs again
+            if job.stopped && !signal.stops() && signal != Signal::Continue {
+                job.signal(Signal::Continue);
+            }
+        }
+
+        exit_code
+    }
+
+    /// Resolve a job spec such as `%2` to a job number
+    ///
+    /// Without a spec, or with `%%` or `%+`, this is the most recent job.
+    fn parse_job_spec(&self, spec: Option<&String>) -> Result<usize, String> {
+        let number = match spec.map(String::as_str) {
+            None | Some("%%" | "%+") => self.jobs.keys().next_back().copied(),
+            Some(spec) => spec.strip_prefix('%')
+                .ok_or_else(|| format!("{}: not a job spec", spec))?
+                .parse()
+                .ok(),
+        };
+
+        number
+            .filter(|number| self.jobs.contains_key(number))
+            .ok_or_else(|| format!("{}: no such job", spec.map_or("current", String::as_str)))
+    }
+
+    /// Get the session environment sorted by name
+    fn sorted_env(&self) -> Vec<(String, String)> {
+        let mut vars: Vec<(String, String)> = self.env.iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        vars.sort();
+        vars
+    }
+
+    /// Preview what a word would expand to if the command ran now
+    ///
+    /// At most `limit` results are returned.
+    pub fn preview_expansion(&self, word: &str, limit: usize) -> Vec<String> {
+        let ctx = ExpandContext {
+            env: &self.env,
+            last_exit_code: self.last_exit_code,
+        };
+
+        expand::expand_word_limited(word, &ctx, limit)
+    }
+
+    /// Expand variables, tildes and globs in every word, and variables and
+    /// tildes in redirection targets, removing quotes as the last step
+    ///
+    /// Unquoted words that expand to nothing are dropped, as are stages left
+    /// without a program.
+    fn expand_pipeline(&self, pipeline: &Pipeline) -> Pipeline {
+        let ctx = ExpandContext {
+            env: &self.env,
+            last_exit_code: self.last_exit_code,
+        };
+
+        let commands = pipeline.commands.iter()
+            .map(|command| SimpleCommand {
+                words: command.words.iter()
+                    .flat_map(|word| expand::expand_word(word, &ctx))
+                    .collect(),
+                redirects: command.redirects.iter()
+                    .map(|redirect| redirect.map_path(|path| expand::expand_path(path, &ctx)))
+                    .collect(),
+            })
+            .filter(|command| !command.words.is_empty())
+            .collect();
+
+        Pipeline { commands }
+    }
+
+    /// Run a built-in command, returning its exit code
+    ///
+    /// Returns `None` if the pipeline is not a built-in command.
+    fn run_builtin(&mut self, pipeline: &Pipeline, out: &mut ExecutionResult) -> Option<i32> {
+        let [simple] = pipeline.commands.as_slice() else {
+            return None;
+        };
+
+        let args = &simple.words[1..];
+        let result = match simple.words[0].as_str() {
+            "cd" => self.handle_cd_command(args, out),
+            "pushd" => self.handle_pushd_command(args, out),
+            "popd" => self.handle_popd_command(args, out),
+            "dirs" => self.handle_dirs_command(args, out),
+            "which" => Ok(self.handle_which_command(args, out)),
+            "type" => Ok(self.handle_type_command(args, out)),
+            "alias" => Ok(self.handle_alias_command(args, out)),
+            "unalias" => Ok(self.handle_unalias_command(args, out)),
+            "export" => Ok(self.handle_export_command(args, out)),
+            "unset" => Ok(self.handle_unset_command(args)),
+            // `env` with arguments runs a program, which is left to the real env
+            "env" if args.is_empty() => Ok(self.handle_env_command(out)),
+            "jobs" => Ok(self.handle_jobs_command(out)),
+            "fg" => Ok(self.handle_fg_command(args, out)),
+            "bg" => Ok(self.handle_bg_command(args, out)),
+            // Process IDs are left to the real kill
+            "kill" if args.iter().any(|arg| arg.starts_with('%')) => Ok(self.handle_kill_command(args, out)),
+            // Other sudo commands go through the password prompt
+            "sudo" if matches!(args, [flag] if matches!(flag.as_str(), "-k" | "-K")) => Ok(self.handle_sudo_command(&args[0], out)),
+            _ => return None,
+        };
+
+        Some(result.unwrap_or_else(|e| {
+            out.push_stderr(format!("Error: {}", e));
+            1
+        }))
+    }
+
+    /// Execute a command line asynchronously
+    ///
+    /// A command still running in the foreground carries on as a background job.
+    pub fn execute(&mut self, command: &str) -> Result<()> {
+        // A new command line ends watching
+        self.watch = None;
+        self.remember_command(command);
+        if command.split_whitespace().next() != Some("watch") {
+            self.last_command = Some(command.to_string());
+        }
+
+        let job = self.start_foreground(command);
+        self.run_line(job, command);
+        Ok(())
+    }
+
+    /// Run a file's lines as a new foreground job, like `source`
+    ///
+    /// This is how the rc file runs: always parsed here, even with the shell
+    /// backend, so the aliases, variables and directory it sets stay in the
+    /// session, and without becoming the last command or its summary.
+    pub fn source_file(&mut self, file: &Path) {
+        let command = format!("source {}", input::quote_literal(&file.to_string_lossy()));
+        let mut job = self.start_foreground(&command);
+        job.startup = true;
+        self.run_list(job, &command);
+    }
+
+    /// Run a command line as a new foreground job
+    fn run_line(&mut self, mut job: Job, command: &str) {
+        // The shell backend hands the line to the user's shell instead
+        if self.config.backend == Backend::Shell && !self.is_session_command(command) {
+            self.run_in_shell(&mut job, command);
+            self.foreground = job;
+            return;
+        }
+        self.run_list(job, command);
+    }
+
+    /// Run a command line as a new foreground job, parsing it here
+    fn run_list(&mut self, mut job: Job, command: &str) {
+        // Parse the command line into a list of pipelines, once aliases and
+        // functions are replaced with what they stand for
+        let list = match parser::parse_command_line(&self.aliases.expand_line(command)) {
+            Ok(list) => list,
+            Err(e) => {
+                job.result.push_stderr(format!("Error: {}", e));
+                job.result.exit_code = Some(2);
+                self.foreground = job;
+                return;
+            }
+        };
+
+        // Only report individual exit codes when there is more than one command
+        job.report_status = list.items.len() > 1;
+        job.pending = list.items.into();
+        self.run_next(&mut job);
+        self.foreground = job;
+
+        // `fg` takes effect once the command that ran it is done
+        if let Some(number) = self.resume.take() {
+            self.resume_job(number);
+        }
+    }
+
+    /// Check whether a command line is a built-in that keeps running here
+    /// with the shell backend, such as `jobs` or `pushd`
+    fn is_session_command(&self, command: &str) -> bool {
+        let Ok(list) = parser::parse_command_line(&self.aliases.expand_line(command)) else {
+            return false;
+        };
+        let [item] = list.items.as_slice() else {
+            return false;
+        };
+        let [simple] = item.pipeline.commands.as_slice() else {
+            return false;
+        };
+
+        !item.background
+            && (SESSION_BUILTINS.contains(&simple.words[0].as_str())
+                || (simple.words[0] == "kill" && simple.words.iter().any(|word| word.starts_with('%'))))
+    }
+
+    /// Get the shell the shell backend hands command lines to
+    fn shell(&self) -> String {
+        self.config.shell.clone()
+            .or_else(|| self.env.get("SHELL").cloned())
+            .filter(|shell| !shell.trim().is_empty())
+            .unwrap_or_else(|| "/bin/sh".to_string())
+    }
+
+    /// Run a command line with `$SHELL -c`
+    ///
+    /// The shell reports its directory and environment after the command,
+    /// which the session takes on once it exits. A full-screen program gets
+    /// the real terminal, so nothing can be reported back from it.
+    fn run_in_shell(&mut self, job: &mut Job, command: &str) {
+        let shell_pipeline = |script: String| Pipeline {
+            commands: vec![SimpleCommand {
+                words: vec![self.shell(), "-c".to_string(), script],
+                redirects: Vec::new(),
+            }],
+        };
+
+        if self.is_fullscreen_command(command) {
+            job.running = Some(command.to_string());
+            job.fullscreen = Some(shell_pipeline(command.to_string()));
+            return;
+        }
+
+        let capture = ShellCapture::new();
+        let pipeline = shell_pipeline(capture.script(command));
+        let limits = self.limits(&pipeline, None);
+        job.shell = Some(capture);
+        self.spawn_pipeline(job, pipeline, command.to_string(), limits);
+    }
+
+    /// Take on the directory and environment a shell was left with
+    ///
+    /// The shell keeps `$PWD` and `$OLDPWD` up to date itself.
+    fn apply_shell_state(&mut self, state: ShellState, out: &mut ExecutionResult) {
+        if let Err(e) = std::env::set_current_dir(&state.cwd) {
+            out.push_stderr(format!("Error: {}: {}", state.cwd.display(), e));
+        }
+        self.env = state.env;
+        self.update_dir_env(out);
+    }
+
+    /// Make way for a new foreground job
+    ///
+    /// The current foreground job moves to the job table if it is still
+    /// running, and jobs listed as done since the last command are dropped.
+    fn start_foreground(&mut self, command: &str) -> Job {
+        let previous = std::mem::take(&mut self.foreground);
+        if previous.is_running() {
+            self.add_job(previous);
+        }
+
+        self.jobs.retain(|_, job| !(job.reported && matches!(job.state(), JobState::Done(_))));
+        for job in self.jobs.values_mut() {
+            job.reported = matches!(job.state(), JobState::Done(_));
+        }
+
+        self.selected = None;
+        self.suggestions = None;
+        Job::new(command)
+    }
+
+    /// Add a job to the job table, returning its number
+    ///
+    /// A job keeps the number it had before if that is still free, otherwise
+    /// it gets the lowest free one.
+    fn add_job(&mut self, mut job: Job) -> usize {
+        let number = job.number
+            .filter(|number| !self.jobs.contains_key(number))
+            .unwrap_or_else(|| (1..).find(|number| !self.jobs.contains_key(number)).unwrap_or_default());
+
+        job.number = Some(number);
+        job.background = true;
+        self.jobs.insert(number, job);
+        number
+    }
+
+    /// Start a `&` chain of pipelines as a new background job, returning its number
+    fn start_background(&mut self, items: Vec<ListItem>) -> usize {
+        let list = CommandList { items };
+        let mut job = Job::new(&list.to_string());
+        job.background = true;
+        job.report_status = list.items.len() > 1;
+        // Inside the job the pipelines run one after the other as usual
+        job.pending = list.items.into_iter()
+            .map(|item| ListItem { background: false, ..item })
+            .collect();
+
+        self.run_next(&mut job);
+        self.add_job(job)
+    }
+
+    /// Bring a job from the job table to the foreground, resuming it if stopped
+    fn resume_job(&mut self, number: usize) {
+        let Some(mut job) = self.jobs.remove(&number) else {
+            return;
+        };
+
+        let previous = std::mem::take(&mut self.foreground);
+        if previous.is_running() {
+            self.add_job(previous);
+        }
+
+        if job.stopped {
+            job.signal(Signal::Continue);
+        }
+        job.background = false;
+        self.foreground = job;
+        self.selected = None;
+    }
+
+    /// Stop the foreground job and move it to the job table, like Ctrl+Z in a shell
+    ///
+    /// The output pane keeps showing the stopped job. Returns its number, if
+    /// anything was running.
+    pub fn suspend(&mut self) -> Option<usize> {
+        // A command in a pty leads an orphaned process group, for which the
+        // kernel discards SIGTSTP, so it has to be stopped outright
+        let signal = if self.foreground.pty_master.is_some() { Signal::Stop } else { Signal::Suspend };
+        if !self.foreground.is_running() || !self.foreground.signal(signal) {
+            return None;
+        }
+
+        self.watch = None;
+        let mut job = std::mem::take(&mut self.foreground);
+        job.pending.clear();
+        let number = self.add_job(job);
+        if let Some(job) = self.jobs.get_mut(&number) {
+            job.result.push_stdout(format!("[{}] Stopped, use fg or bg to resume", number));
+        }
+
+        self.selected = Some(number);
+        Some(number)
+    }
+
+    /// Start the next pipeline in a job's command list whose condition holds
+    ///
+    /// Built-in commands finish immediately, so this keeps going until an
+    /// external pipeline is running or the list is exhausted.
+    fn run_next(&mut self, job: &mut Job) {
+        while let Some(item) = self.next_item(job) {
+            // Short-circuit on the exit code of the previous pipeline
+            if !item.should_run(job.result.exit_code) {
+                if job.report_status {
+                    job.result.push_stdout(format!("[skipped] {}", item.pipeline));
+                }
+                continue;
+            }
+
+            // A chain ending in `&` runs as a job of its own
+            if item.background {
+                let mut chain = vec![item];
+                while let Some(next) = job.pending.pop_front() {
+                    if !next.background || next.connector == Connector::Always {
+                        job.pending.push_front(next);
+                        break;
+                    }
+                    chain.push(next);
+                }
+
+                let number = self.start_background(chain);
+                let command = &self.jobs[&number].command;
+                job.result.push_stdout(format!("[{}] {}", number, command));
+
+                // Starting a job always succeeds
+                job.result.exit_code = Some(0);
+                if !job.background {
+                    self.last_exit_code = Some(0);
+                }
+                continue;
+            }
+
+            // `watch` keeps its command unexpanded, to expand it afresh on each run
+            if item.pipeline.commands[0].words.first().is_some_and(|word| word == "watch") {
+                let exit_code = self.handle_watch_command(job, &item.pipeline);
+                self.finish_pipeline(job, &item.pipeline.to_string(), Completion::exited(exit_code));
+                continue;
+            }
+
+            // Expand variables now, so `$?` sees the previous pipeline
+            let pipeline = self.expand_pipeline(&item.pipeline);
+            if pipeline.commands.is_empty() {
+                self.finish_pipeline(job, &item.pipeline.to_string(), Completion::exited(0));
+                continue;
+            }
+
+            // `source file` queues the file's lines to run next
+            if let [simple] = pipeline.commands.as_slice()
+                && matches!(simple.words[0].as_str(), "source" | ".")
+            {
+                let exit_code = self.handle_source_command(job, &simple.words[1..]);
+                self.finish_pipeline(job, &item.pipeline.to_string(), Completion::exited(exit_code));
+                continue;
+            }
+
+            // `timeout DURATION command` limits how long the pipeline may run
+            let (pipeline, timeout) = Self::strip_timeout(pipeline);
+
+            if let Some(exit_code) = self.run_builtin(&pipeline, &mut job.result) {
+                self.finish_pipeline(job, &item.pipeline.to_string(), Completion::exited(exit_code));
+                continue;
+            }
+
+            // Say which command is missing, with corrections, instead of running the pipeline
+            if let Some(missing) = self.missing_command(&pipeline) {
+                self.report_missing_command(job, &missing);
+                self.finish_pipeline(job, &item.pipeline.to_string(), Completion::exited(127));
+                continue;
+            }
+
+            // Only the UI can give a full-screen program the real terminal
+            if !job.background && self.is_fullscreen(&pipeline) {
+                job.running = Some(item.pipeline.to_string());
+                job.fullscreen = Some(pipeline);
+                return;
+            }
+
+            let limits = self.limits(&pipeline, timeout);
+            self.spawn_pipeline(job, pipeline, item.pipeline.to_string(), limits);
+            return;
+        }
+    }
+
+    /// Get the next pipeline of a job's command list
+    ///
+    /// Once the list is done, the next line of a sourced file is parsed into
+    /// a new list. Aliases defined by earlier lines apply, and a line that
+    /// doesn't parse is reported with its line number and skipped.
+    fn next_item(&mut self, job: &mut Job) -> Option<ListItem> {
+        while job.pending.is_empty() {
+            match job.script.pop_front()? {
+                Script::Line { file, number, text } => {
+                    let location = format!("{}:{}", file, number);
+                    match parser::parse_command_line(&self.aliases.expand_line(&text)) {
+                        Ok(list) => job.pending = list.items.into(),
+                        Err(e) => {
+                            job.result.push_stderr(format!("{}: Error: {}", location, e));
+                            job.result.exit_code = Some(2);
+                        }
+                    }
+                    job.script_line = Some(location);
+                }
+                Script::Rest(items) => {
+                    job.pending = items;
+                    job.script_line = None;
+                }
+            }
+        }
+        job.pending.pop_front()
+    }
+
+    /// Handle the source command, returning its exit code
+    ///
+    /// The file's lines run one after another, just as if they were typed,
+    /// before the rest of the command line. Blank lines and lines starting
+    /// with `#` are skipped.
+    fn handle_source_command(&mut self, job: &mut Job, args: &[String]) -> i32 {
+        let Some(file) = args.first() else {
+            job.result.push_stderr("source: filename argument required");
+            return 2;
+        };
+
+        // A file that sources itself would never finish
+        if job.sourced >= MAX_SOURCED_FILES {
+            job.result.push_stderr(format!("source: {}: too many files sourced", file));
+            return 1;
+        }
+
+        let contents = match std::fs::read_to_string(file) {
+            Ok(contents) => contents,
+            Err(e) => {
+                job.result.push_stderr(format!("source: {}: {}", file, e));
+                return 1;
+            }
+        };
+        job.sourced += 1;
+
+        // The rest of the current line waits for the file to finish
+        if !job.pending.is_empty() {
+            job.script.push_front(Script::Rest(std::mem::take(&mut job.pending)));
+        }
+
+        let lines: Vec<Script> = contents.lines()
+            .enumerate()
+            .filter(|(_, text)| !text.trim().is_empty() && !text.trim_start().starts_with('#'))
+            .map(|(idx, text)| Script::Line {
+                file: file.clone(),
+                number: idx + 1,
+                text: text.to_string(),
+            })
+            .collect();
+        for line in lines.into_iter().rev() {
+            job.script.push_front(line);
         }
+        0
     }
-}
 
-/// Command executor
-pub struct Executor {
-    /// Channel for receiving command output
-    output_rx: Option<Receiver<ExecutionOutput>>,
-    /// Channel for sending termination signal
-    terminate_tx: Option<Sender<()>>,
-    /// Current execution result
-    result: ExecutionResult,
-    /// Timestamp of the last sudo command (for caching)
-    sudo_timestamp: Option<std::time::Instant>,
-}
+    /// Handle the watch command, returning its exit code
+    ///
+    /// `watch [-n DURATION | -f] [command]` runs the command again every
+    /// interval, or whenever a file under the current directory changes,
+    /// until another command line runs or Ctrl+C is pressed. Without a
+    /// command it watches the last command line typed.
+    fn handle_watch_command(&mut self, job: &mut Job, pipeline: &Pipeline) -> i32 {
+        if job.background {
+            job.result.push_stderr("watch: can't watch a command in the background");
+            return 1;
+        }
 
-/// Type of output from command execution
-#[derive(Debug, Clone)]
-pub enum ExecutionOutput {
-    /// Standard output line
-    Stdout(String),
-    /// Standard error line
-    Stderr(String),
-    /// Command finished with exit code
-    Finished(Option<i32>),
-}
+        let mut pipeline = pipeline.clone();
+        let words = &mut pipeline.commands[0].words;
+        let mut trigger = Trigger::Interval(watch::DEFAULT_INTERVAL);
+        let mut idx = 1;
+        while let Some(word) = words.get(idx) {
+            match word.as_str() {
+                "-n" | "--interval" => {
+                    let interval = words.get(idx + 1)
+                        .and_then(|word| config::parse_duration(word))
+                        .filter(|interval| !interval.is_zero());
+                    let Some(interval) = interval else {
+                        job.result.push_stderr("watch: -n needs an interval, such as 2s or 500ms");
+                        return 2;
+                    };
+                    trigger = Trigger::Interval(interval);
+                    idx += 2;
+                }
+                "-f" | "--files" => {
+                    trigger = Trigger::Files;
+                    idx += 1;
+                }
+                _ => break,
+            }
+        }
+        words.drain(..idx);
+
+        let command = match (words.is_empty(), &self.last_command) {
+            (false, _) => pipeline.to_string(),
+            (true, Some(last)) if pipeline.commands.len() == 1 => last.clone(),
+            _ => {
+                job.result.push_stderr("watch: no command to watch");
+                return 2;
+            }
+        };
 
-impl Default for Executor {
-    fn default() -> Self {
-        Self {
-            output_rx: None,
-            terminate_tx: None,
-            result: ExecutionResult::default(),
-            sudo_timestamp: None,
+        let files = match trigger {
+            Trigger::Files => match std::env::current_dir()
+                .and_then(|cwd| FileWatcher::start(&cwd, self.notifier.clone()).map_err(io::Error::other))
+            {
+                Ok(files) => Some(files),
+                Err(e) => {
+                    job.result.push_stderr(format!("watch: {}", e));
+                    return 1;
+                }
+            },
+            Trigger::Interval(_) => None,
+        };
+
+        // The first run starts once the rest of the line is done
+        self.watch = Some(Watch::new(command, trigger, files));
+        0
+    }
+
+    /// Take a `timeout DURATION` prefix off the first stage of a pipeline
+    ///
+    /// Anything that doesn't start with a valid duration, such as options
+    /// for the `timeout` program, is left to the real program.
+    fn strip_timeout(mut pipeline: Pipeline) -> (Pipeline, Option<Duration>) {
+        let words = &mut pipeline.commands[0].words;
+        if words.len() < 3 || words[0] != "timeout" {
+            return (pipeline, None);
         }
+        let Some(timeout) = config::parse_duration(&words[1]) else {
+            return (pipeline, None);
+        };
+
+        words.drain(..2);
+        (pipeline, Some(timeout))
     }
-}
 
-impl Executor {
-    /// Create a new command executor
-    pub fn new() -> Self {
-        Self::default()
+    /// Work out the limits an external pipeline runs under
+    ///
+    /// A `timeout` prefix wins over the rules for the pipeline's programs,
+    /// which win over the default timeout. A limit of zero means none.
+    fn limits(&self, pipeline: &Pipeline, timeout: Option<Duration>) -> Limits {
+        let timeout = match timeout {
+            Some(timeout) => Some((timeout, "timeout prefix".to_string())),
+            None => pipeline.commands.iter()
+                .filter_map(|command| {
+                    let program = Path::new(&command.words[0]).file_name()?.to_str()?;
+                    let limit = self.config.timeouts.get(program)?;
+                    Some((limit.0, format!("execution.timeouts.{}", program)))
+                })
+                .min_by_key(|(limit, _)| *limit)
+                .or_else(|| self.config.timeout.map(|limit| (limit.0, "execution.timeout".to_string()))),
+        };
+
+        Limits {
+            timeout: timeout.filter(|(limit, _)| !limit.is_zero()),
+            max_output_lines: self.config.max_output_lines.filter(|max| *max > 0),
+        }
     }
 
-    /// Handle the cd command
-    fn handle_cd_command(&mut self, args: &[String]) -> Result<()> {
-        // Get the target directory
-        let target_dir = if args.is_empty() {
-            // If no arguments, cd to home directory
-            dirs::home_dir().ok_or_else(|| anyhow::anyhow!("Could not determine home directory"))?
-        } else {
-            std::path::PathBuf::from(&args[0])
+    /// Check whether a pipeline is a single full-screen program, possibly run with sudo
+    fn is_fullscreen(&self, pipeline: &Pipeline) -> bool {
+        let [simple] = pipeline.commands.as_slice() else {
+            return false;
         };
+        if !simple.redirects.is_empty() {
+            return false;
+        }
 
-        // Change the current directory
-        match std::env::set_current_dir(&target_dir) {
-            Ok(_) => {
-                // Success - add current directory to output
-                let current_dir = std::env::current_dir()?;
-                self.result.stdout.push(format!("Changed directory to: {}", current_dir.display()));
+        // Look past `sudo` and its options to the program it runs
+        let program = match simple.words.split_first() {
+            Some((sudo, args)) if sudo == "sudo" => sudo::program_index(args).map(|idx| &args[idx]),
+            first => first.map(|(program, _)| program),
+        };
 
-                // Automatically run ls after changing directory
-                if let Ok(ls_output) = self.run_command_sync("ls", &[]) {
-                    // Add ls output to the result
-                    self.result.stdout.extend(ls_output.stdout);
-                    self.result.stderr.extend(ls_output.stderr);
-                }
+        // The editor opened from the file list always gets the whole terminal
+        let editor = self.editor();
+        let editor = editor.split_whitespace().next().map(|editor| Path::new(editor).file_name());
+
+        program
+            .and_then(|program| Path::new(program).file_name())
+            .is_some_and(|name| {
+                self.config.fullscreen.iter().any(|fullscreen| name == fullscreen.as_str()) || editor == Some(Some(name))
+            })
+    }
 
-                self.result.exit_code = Some(0);
+    /// Get the editor used to open files
+    fn editor(&self) -> String {
+        self.config.editor.clone()
+            .or_else(|| self.env.get("VISUAL").cloned())
+            .or_else(|| self.env.get("EDITOR").cloned())
+            .filter(|editor| !editor.trim().is_empty())
+            .unwrap_or_else(|| "nano".to_string())
+    }
+
+    /// Build the command line that opens a file in the editor
+    pub fn edit_command(&self, path: &str) -> String {
+        format!("{} {}", self.editor(), input::quote_literal(path))
+    }
+
+    /// Check whether a command line runs a full-screen program
+    ///
+    /// Such commands talk to the terminal directly, including sudo's own
+    /// password prompt.
+    pub fn is_fullscreen_command(&self, command: &str) -> bool {
+        parser::parse_command_line(&self.aliases.expand_line(command)).is_ok_and(|list| {
+            list.items.iter().any(|item| self.is_fullscreen(&self.expand_pipeline(&item.pipeline)))
+        })
+    }
+
+    /// Take the full-screen command waiting to run, if any
+    ///
+    /// The caller suspends the UI, runs the command with the real terminal
+    /// and reports back with [`Executor::finish_fullscreen`].
+    pub fn take_fullscreen(&mut self) -> Option<Command> {
+        let pipeline = self.foreground.fullscreen.take()?;
+        let simple = &pipeline.commands[0];
+
+        let mut cmd = Command::new(&simple.words[0]);
+        cmd.args(&simple.words[1..])
+            .env_clear()
+            .envs(&self.env);
+        process::foreground(&mut cmd);
+        Some(cmd)
+    }
+
+    /// Record how a full-screen command ended and carry on with the command list
+    pub fn finish_fullscreen(&mut self, status: io::Result<(ExitStatus, ResourceUsage)>) {
+        let mut job = std::mem::take(&mut self.foreground);
+        let description = job.running.take().unwrap_or_default();
+        let completion = match status {
+            Ok((status, usage)) => Completion {
+                exit_code: status.code(),
+                signal: process::exit_signal(&status),
+                usage: Some(usage),
             },
             Err(e) => {
-                // Error - add error message to output
-                self.result.stderr.push(format!("Failed to change directory: {}", e));
-                self.result.exit_code = Some(1);
+                job.result.push_stderr(format!("Error: {}: {}", description, e));
+                Completion::exited(127)
             }
-        }
+        };
 
-        Ok(())
+        self.finish_pipeline(&mut job, &description, completion);
+        self.run_next(&mut job);
+        self.foreground = job;
     }
 
-    /// Execute a command asynchronously
-    pub fn execute(&mut self, command: &str) -> Result<()> {
-        // Cancel any running command
-        self.terminate();
-
-        // Reset the result
-        self.result = ExecutionResult::default();
-
-        // Clone the command string to avoid borrowing issues
-        let command = command.to_string();
+    /// Record how a finished pipeline ended
+    ///
+    /// Only foreground jobs update `$?`. External pipelines get a summary
+    /// line with the time and resources they used; in a command list every
+    /// pipeline gets one, naming the pipeline.
+    fn finish_pipeline(&mut self, job: &mut Job, description: &str, completion: Completion) {
+        job.result.exit_code = completion.exit_code;
+        job.result.signal = completion.signal;
+        job.result.usage = completion.usage;
+        if !job.background {
+            self.last_exit_code = completion.exit_code;
+            if !job.startup {
+                self.last_summary = completion.usage.map(|_| job.result.summary());
+            }
+        }
 
-        // Split the command into program and arguments
-        let mut parts = command.split_whitespace();
-        let program = parts.next().ok_or_else(|| anyhow::anyhow!("Empty command"))?.to_string();
-        let args: Vec<String> = parts.map(|s| s.to_string()).collect();
+        if job.report_status {
+            job.result.push_stdout(format!("[{}] {}", job.result.summary(), description));
+        } else if completion.usage.is_some() {
+            job.result.push_stdout(format!("[{}]", job.result.summary()));
+        }
 
-        // Handle built-in commands
-        if program == "cd" {
-            return self.handle_cd_command(&args);
+        // Point failures in a sourced file back at the line they came from
+        if let Some(location) = &job.script_line
+            && completion.exit_code != Some(0)
+        {
+            job.result.push_stderr(format!("{}: {} failed", location, description));
         }
+    }
 
-        // Handle sudo command
-        if program == "sudo" && !args.is_empty() {
-            // Check if we have a valid sudo session
-            if self.is_sudo_session_valid() {
-                // Update the sudo timestamp
-                self.sudo_timestamp = Some(std::time::Instant::now());
+    /// Start an external pipeline of a job on a background thread
+    ///
+    /// `description` is the pipeline as typed, before expansion.
+    fn spawn_pipeline(&mut self, job: &mut Job, mut pipeline: Pipeline, description: String, limits: Limits) {
+        let mut env = self.env.clone();
+
+        // sudo stages fetch the password typed into the prompt through
+        // askpass. If that can't be set up, sudo asks in its own way.
+        if pipeline.commands.iter().any(is_sudo_stage) {
+            match Askpass::start(job.sudo_password.take()).and_then(|askpass| Ok((askpass.env()?, askpass))) {
+                Ok((askpass_env, askpass)) => {
+                    env.extend(askpass_env);
+                    for command in pipeline.commands.iter_mut().filter(|command| is_sudo_stage(command)) {
+                        command.words.insert(1, "-A".to_string());
+                    }
+                    job.askpass = Some(askpass);
+                }
+                Err(e) => job.result.push_stderr(format!("Error: sudo: {}", e)),
             }
         }
 
         // Create channels for communication
-        let (output_tx, output_rx) = mpsc::channel();
-        let (terminate_tx, terminate_rx) = mpsc::channel();
-
-        self.output_rx = Some(output_rx);
-        self.terminate_tx = Some(terminate_tx);
+        let (output_tx, output_rx) = self.output_channel();
+        let (events_tx, events_rx) = mpsc::channel();
+
+        job.output_rx = Some(output_rx);
+        job.signal_tx = Some(events_tx.clone());
+        job.running = Some(description);
+        job.last_signal = None;
+
+        // Allocate a pty so the command sees a real terminal. sudo keeps
+        // credentials per terminal, so sudo pipelines stay on mouse_term's own.
+        let pty = if job.askpass.is_some() { None } else { self.open_pty() };
+        job.pty_master = pty.as_ref().and_then(|pty| pty.master().try_clone().ok());
+
+        // Keep the write end of the command's stdin so keystrokes can be forwarded
+        let mut stdin_reader = None;
+        job.stdin = match &job.pty_master {
+            Some(master) => master.try_clone().ok().map(|master| Box::new(master) as Box<dyn Write + Send>),
+            None => io::pipe().ok().map(|(reader, writer)| {
+                stdin_reader = Some(reader);
+                Box::new(writer) as Box<dyn Write + Send>
+            }),
+        };
 
         // Spawn a thread to run the command
         thread::spawn(move || {
-            let result = Self::run_command(&program, &args, output_tx.clone(), terminate_rx);
+            let result = Self::run_pipeline(&pipeline, &env, pty, stdin_reader, limits, output_tx.clone(), (events_tx, events_rx));
 
             if let Err(e) = result {
                 // Send the error as stderr
-                let _ = output_tx.send(ExecutionOutput::Stderr(format!("Error: {}", e)));
-                let _ = output_tx.send(ExecutionOutput::Finished(Some(-1)));
+                let _ = output_tx.send(ExecutionOutput::line(Stream::Stderr, format!("Error: {}", e)));
+                let _ = output_tx.send(ExecutionOutput::Finished(Completion::exited(-1)));
             }
         });
+    }
 
+    /// Execute a command line with sudo in it, giving sudo the password
+    /// through askpass
+    ///
+    /// The line runs like any other, with the password going to its first
+    /// sudo command. Without a password sudo is expected to have cached
+    /// credentials. If it asks for a password anyway, or turns the one given
+    /// down, the line is handed back through `take_sudo_prompt` to ask again.
+    pub fn execute_sudo(&mut self, command: &str, password: Option<Secret>) -> Result<()> {
+        self.watch = None;
+        let mut job = self.start_foreground(command);
+        job.sudo_password = password;
+
+        // The password prompt is for sudo run here, even with the shell backend
+        self.run_list(job, command);
         Ok(())
     }
 
-    /// Execute a sudo command with a password
-    pub fn execute_sudo(&mut self, command: &str, password: &str) -> Result<()> {
-        // Cancel any running command
-        self.terminate();
+    /// Check whether a command line runs sudo through the password prompt
+    ///
+    /// Full-screen programs ask for the password on the terminal themselves,
+    /// and `sudo -k` and `sudo -K` never need one.
+    pub fn is_sudo_command(&self, command: &str) -> bool {
+        parser::parse_command_line(&self.aliases.expand_line(command)).is_ok_and(|list| {
+            list.items.iter().any(|item| {
+                let pipeline = self.expand_pipeline(&item.pipeline);
+                let clears_credentials = matches!(
+                    pipeline.commands.as_slice(),
+                    [simple] if matches!(simple.words.as_slice(), [_, flag] if matches!(flag.as_str(), "-k" | "-K"))
+                );
+                pipeline.commands.iter().any(is_sudo_stage) && !clears_credentials && !self.is_fullscreen(&pipeline)
+            })
+        })
+    }
 
-        // Reset the result
-        self.result = ExecutionResult::default();
+    /// Check whether sudo would run a command without asking for a password
+    pub fn sudo_credentials_cached(&self) -> bool {
+        sudo::credentials_cached(&self.env)
+    }
 
-        // Clone the command string to avoid borrowing issues
-        let command = command.to_string();
-        let password = password.to_string();
+    /// Take a sudo command that needs its password asked for again
+    pub fn take_sudo_prompt(&mut self) -> Option<SudoPrompt> {
+        self.sudo_prompt.take()
+    }
 
-        // Create channels for communication
-        let (output_tx, output_rx) = mpsc::channel();
-        let (terminate_tx, terminate_rx) = mpsc::channel();
+    /// Run a pipeline and capture its output
+    ///
+    /// Each stage's stdout feeds the next stage's stdin. The last stage's
+    /// stdout and the stderr of every stage are captured unless redirected.
+    /// With a pty, captured streams share the terminal and are reported as
+    /// stdout. Signals for the stages arrive on `events`, and the stages are
+    /// stopped if they run past `limits`.
+    fn run_pipeline(
+        pipeline: &Pipeline,
+        env: &HashMap<String, String>,
+        pty: Option<Pty>,
+        stdin: Option<io::PipeReader>,
+        limits: Limits,
+        output_tx: OutputSender,
+        events: (Sender<PipelineEvent>, Receiver<PipelineEvent>),
+    ) -> Result<()> {
+        let (events_tx, events_rx) = events;
+        let mut reader_threads = Vec::new();
+        let watchdog = Watchdog::new(limits, events_tx.clone());
 
-        self.output_rx = Some(output_rx);
-        self.terminate_tx = Some(terminate_tx);
+        // Set up where captured output goes
+        let (capture_out, capture_err) = match &pty {
+            Some(pty) => {
+                let master = pty.master().try_clone()?;
+                reader_threads.push(Self::spawn_reader(master, output_tx.clone(), Stream::Stdout, watchdog.counter()));
 
-        // Update the sudo timestamp
-        self.sudo_timestamp = Some(std::time::Instant::now());
+                (Sink::File(pty.slave().try_clone()?), Sink::File(pty.slave().try_clone()?))
+            }
+            None => {
+                let (stdout_reader, stdout_writer) = io::pipe()?;
+                let (stderr_reader, stderr_writer) = io::pipe()?;
+                reader_threads.push(Self::spawn_reader(stdout_reader, output_tx.clone(), Stream::Stdout, watchdog.counter()));
+                reader_threads.push(Self::spawn_reader(stderr_reader, output_tx.clone(), Stream::Stderr, watchdog.counter()));
 
-        // Spawn a thread to run the command
-        thread::spawn(move || {
-            // Create a command that uses sudo with password from stdin
-            let mut cmd = Command::new("sudo");
-            cmd.arg("-S") // Read password from stdin
-                .args(command.split_whitespace().skip(1)) // Skip the "sudo" part
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped());
-
-            // Start the command
-            match cmd.spawn() {
-                Ok(mut child) => {
-                    // Write the password to stdin
-                    if let Some(mut stdin) = child.stdin.take() {
-                        if let Err(e) = stdin.write_all(format!("{}\n", password).as_bytes()) {
-                            let _ = output_tx.send(ExecutionOutput::Stderr(format!("Failed to write password: {}", e)));
-                            let _ = output_tx.send(ExecutionOutput::Finished(Some(-1)));
-                            return;
-                        }
+                (Sink::Pipe(stdout_writer), Sink::Pipe(stderr_writer))
+            }
+        };
+
+        // Start every stage, connecting each one to the next with a pipe
+        let started = Instant::now();
+        let mut children = Vec::new();
+        let mut previous_stdout = stdin;
+        let mut failed_exit_code = None;
+        let last = pipeline.commands.len() - 1;
+
+        for (idx, command) in pipeline.commands.iter().enumerate() {
+            let stdin = previous_stdout.take();
+            let stdout = if idx < last {
+                let (reader, writer) = io::pipe()?;
+                previous_stdout = Some(reader);
+                Sink::Pipe(writer)
+            } else {
+                capture_out.try_clone()?
+            };
+
+            let started = Self::build_stage(command, env, pty.as_ref(), stdin, stdout, capture_err.try_clone()?)
+                .map_err(|e| (1, e))
+                .and_then(|mut cmd| cmd.spawn().map_err(|e| (127, e.into())));
+
+            match started {
+                Ok(child) => children.push(child),
+                Err((code, e)) => {
+                    let _ = output_tx.send(ExecutionOutput::line(Stream::Stderr, format!("Error: {}: {}", command.words[0], e)));
+                    if idx == last {
+                        failed_exit_code = Some(code);
                     }
+                }
+            }
+        }
 
-                    // Get stdout and stderr
-                    let stdout = child.stdout.take().unwrap_or_else(|| panic!("Failed to capture stdout"));
-                    let stderr = child.stderr.take().unwrap_or_else(|| panic!("Failed to capture stderr"));
-
-                    // Create readers
-                    let stdout_reader = BufReader::new(stdout);
-                    let stderr_reader = BufReader::new(stderr);
-
-                    // Clone the sender for the threads
-                    let stderr_tx = output_tx.clone();
-                    let stdout_tx = output_tx.clone();
-
-                    // Spawn a thread to read stdout
-                    let stdout_thread = thread::spawn(move || {
-                        for line in stdout_reader.lines() {
-                            if let Ok(line) = line {
-                                if stdout_tx.send(ExecutionOutput::Stdout(line)).is_err() {
-                                    break;
-                                }
-                            }
-                        }
-                    });
-
-                    // Spawn a thread to read stderr
-                    let stderr_thread = thread::spawn(move || {
-                        for line in stderr_reader.lines() {
-                            if let Ok(line) = line {
-                                if stderr_tx.send(ExecutionOutput::Stderr(line)).is_err() {
-                                    break;
-                                }
-                            }
-                        }
-                    });
-
-                    // Wait for the command to finish or be terminated
-                    let exit_status = loop {
-                        // Check if we should terminate
-                        if terminate_rx.try_recv().is_ok() {
-                            // Kill the process
-                            let _ = child.kill();
-                            break None;
-                        }
+        // Drop our copies of the pipes and the terminal so reads end when the stages exit
+        drop(capture_out);
+        drop(capture_err);
+        drop(pty);
 
-                        // Check if the process has finished
-                        match child.try_wait() {
-                            Ok(Some(status)) => break Some(status),
-                            Ok(None) => {
-                                // Process still running, sleep a bit
-                                thread::sleep(std::time::Duration::from_millis(10));
-                            }
-                            Err(_) => break None,
-                        }
-                    };
+        // Wait for every stage to finish or be terminated, adding up what they used
+        let (exit_status, mut usage) = Self::supervise(children, watchdog, &events_tx, &events_rx, &output_tx);
 
-                    // Wait for the reader threads to finish
-                    let _ = stdout_thread.join();
-                    let _ = stderr_thread.join();
+        usage.wall = started.elapsed();
 
-                    // Send the finished message
-                    let exit_code = exit_status.and_then(|s| s.code());
-                    let _ = output_tx.send(ExecutionOutput::Finished(exit_code));
-                }
-                Err(e) => {
-                    // Send the error as stderr
-                    let _ = output_tx.send(ExecutionOutput::Stderr(format!("Failed to start command: {}", e)));
-                    let _ = output_tx.send(ExecutionOutput::Finished(Some(-1)));
+        // Wait for the reader threads to finish
+        for reader_thread in reader_threads {
+            let _ = reader_thread.join();
+        }
+
+        // Send the finished message
+        let completion = Completion {
+            exit_code: failed_exit_code.or_else(|| exit_status.and_then(|s| s.code())),
+            signal: exit_status.filter(|_| failed_exit_code.is_none()).and_then(|s| process::exit_signal(&s)),
+            usage: Some(usage),
+        };
+        let _ = output_tx.send(ExecutionOutput::Finished(completion));
+
+        Ok(())
+    }
+
+    /// Wait for the stages of a pipeline to exit, passing on signals and
+    /// stopping them at their limits
+    ///
+    /// Each stage is waited on by a thread of its own that reports to
+    /// `events_rx` once the stage exits, so this sleeps until a stage exits,
+    /// a signal arrives or a limit may have been reached. The stages are
+    /// only reaped here, after which they are never signalled, so a signal
+    /// can't reach a pid that was reused. Where a stage can't be waited on
+    /// without reaping it, the stages are checked every [`EXIT_POLL`]
+    /// instead. Returns the status of the last stage and what the stages
+    /// used together.
+    fn supervise(
+        mut children: Vec<Child>,
+        mut watchdog: Watchdog,
+        events_tx: &Sender<PipelineEvent>,
+        events_rx: &Receiver<PipelineEvent>,
+        output_tx: &OutputSender,
+    ) -> (Option<ExitStatus>, ResourceUsage) {
+        if process::CAN_WAIT_EXITED {
+            for child in &children {
+                let pid = child.id();
+                let events_tx = events_tx.clone();
+                thread::spawn(move || {
+                    let _ = process::wait_exited(pid);
+                    let _ = events_tx.send(PipelineEvent::Exited);
+                });
+            }
+        }
+        let poll = (!process::CAN_WAIT_EXITED).then_some(EXIT_POLL);
+
+        let mut statuses = vec![None; children.len()];
+        let mut usage = ResourceUsage::default();
+        while statuses.iter().any(Option::is_none) {
+            // Holding `events_tx` keeps the channel open, so this only returns
+            // None when the watchdog's deadline has passed or it is time to poll
+            let wake = watchdog.deadline().into_iter().chain(poll.map(|poll| Instant::now() + poll)).min();
+            let event = match wake {
+                Some(wake) => events_rx.recv_timeout(wake.saturating_duration_since(Instant::now())).ok(),
+                None => events_rx.recv().ok(),
+            };
+            let signal = match event {
+                Some(PipelineEvent::Signal(signal)) => Some(signal),
+                Some(PipelineEvent::Exited | PipelineEvent::OutputLimit) | None => None,
+            };
+
+            // Reap the stages that have exited
+            for (child, status) in children.iter_mut().zip(&mut statuses).filter(|(_, status)| status.is_none()) {
+                match process::try_wait_usage(child) {
+                    Ok(Some((exit, stage_usage))) => {
+                        *status = Some(Some(exit));
+                        usage = usage.merge(stage_usage);
+                    }
+                    Ok(None) => {}
+                    Err(_) => *status = Some(None),
                 }
             }
-        });
 
-        Ok(())
+            // Stop the stages once a limit is reached, saying which one
+            let limit_signal = watchdog.check().map(|(signal, reason)| {
+                let _ = output_tx.send(ExecutionOutput::line(Stream::Stderr, reason));
+                signal
+            });
+
+            // Pass on signals to the stages still running
+            for signal in signal.into_iter().chain(limit_signal) {
+                let running = children.iter_mut()
+                    .zip(&statuses)
+                    .filter(|(_, status)| status.is_none())
+                    .map(|(child, _)| child);
+                Self::signal_stages(running, signal);
+            }
+        }
+
+        // The pipeline's status is that of its last stage
+        (statuses.last().copied().flatten().flatten(), usage)
     }
 
-    /// Check if the sudo session is still valid
-    pub fn is_sudo_session_valid(&self) -> bool {
-        if let Some(timestamp) = self.sudo_timestamp {
-            // Sudo session is valid for 15 minutes
-            timestamp.elapsed() < std::time::Duration::from_secs(15 * 60)
+    /// Deliver a signal to the process group of every stage
+    ///
+    /// Where signals aren't supported, any signal that ends a command kills
+    /// the stage outright instead.
+    fn signal_stages<'a>(children: impl IntoIterator<Item = &'a mut Child>, signal: Signal) {
+        let kill_instead = if cfg!(unix) {
+            signal == Signal::Kill
         } else {
-            false
+            !signal.stops() && signal != Signal::Continue
+        };
+        for child in children {
+            if process::signal_group(child, signal).is_err() && kill_instead {
+                let _ = child.kill();
+            }
         }
     }
 
-    /// Run a command and capture its output
-    fn run_command(
-        program: &str,
-        args: &[String],
-        output_tx: Sender<ExecutionOutput>,
-        terminate_rx: Receiver<()>,
-    ) -> Result<()> {
-        // Create the command
-        let mut cmd = Command::new(program);
-        cmd.args(args)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
+    /// Build the process for one pipeline stage with its stdio wired up
+    fn build_stage(
+        command: &SimpleCommand,
+        env: &HashMap<String, String>,
+        pty: Option<&Pty>,
+        stdin: Option<io::PipeReader>,
+        mut stdout: Sink,
+        mut stderr: Sink,
+    ) -> Result<Command> {
+        let mut cmd = Command::new(&command.words[0]);
+        cmd.args(&command.words[1..])
+            .env_clear()
+            .envs(env);
+
+        // Only sudo may fetch the password from askpass
+        if !is_sudo_stage(command) && env.contains_key(sudo::ASKPASS_SOCKET_VAR) {
+            for var in sudo::ASKPASS_VARS {
+                cmd.env_remove(var);
+            }
+        }
 
-        // Start the command
-        let mut child = cmd.spawn()?;
+        // Give the stage the terminal, then override whatever is piped or redirected.
+        // Either way the stage leads its own process group, so signals reach
+        // whatever it spawns too.
+        match pty {
+            Some(pty) => pty.attach(&mut cmd)?,
+            None => process::new_group(&mut cmd),
+        }
+        match stdin {
+            Some(stdin) => {
+                cmd.stdin(stdin);
+            }
+            // Never let a command read from the terminal the UI is using
+            None if pty.is_none() => {
+                cmd.stdin(Stdio::null());
+            }
+            None => {}
+        }
 
-        // Get stdout and stderr
-        let stdout = child.stdout.take().ok_or_else(|| anyhow::anyhow!("Failed to capture stdout"))?;
-        let stderr = child.stderr.take().ok_or_else(|| anyhow::anyhow!("Failed to capture stderr"))?;
+        // Apply redirections in order, so `2>&1` follows the stdout seen so far
+        for redirect in &command.redirects {
+            match redirect {
+                Redirect::Input(path) => {
+                    let file = File::open(path).map_err(|e| anyhow::anyhow!("{}: {}", path, e))?;
+                    cmd.stdin(file);
+                }
+                Redirect::Output { path, append } => {
+                    stdout = Sink::File(Self::open_output(path, *append)?);
+                }
+                Redirect::Error { path, append } => {
+                    stderr = Sink::File(Self::open_output(path, *append)?);
+                }
+                Redirect::ErrorToOutput => {
+                    stderr = stdout.try_clone()?;
+                }
+                Redirect::Both { path, append } => {
+                    let file = Self::open_output(path, *append)?;
+                    stdout = Sink::File(file.try_clone()?);
+                    stderr = Sink::File(file);
+                }
+            }
+        }
 
-        // Create readers
-        let stdout_reader = BufReader::new(stdout);
-        let stderr_reader = BufReader::new(stderr);
+        cmd.stdout(stdout).stderr(stderr);
+        Ok(cmd)
+    }
 
-        // Clone the sender for the threads
-        let stderr_tx = output_tx.clone();
-        let stdout_tx = output_tx.clone();
+    /// Open a file as the target of an output redirection
+    fn open_output(path: &str, append: bool) -> Result<File> {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)
+            .map_err(|e| anyhow::anyhow!("{}: {}", path, e))
+    }
 
-        // Spawn a thread to read stdout
-        let stdout_thread = thread::spawn(move || {
-            for line in stdout_reader.lines() {
-                if let Ok(line) = line {
-                    if stdout_tx.send(ExecutionOutput::Stdout(line)).is_err() {
-                        break;
-                    }
+    /// Spawn a thread that forwards each line read from `reader` to the output channel
+    ///
+    /// Lines are timestamped as they are read, and every reader shares one
+    /// channel, so the two streams keep the order they were written in. A
+    /// line still being written is sent as it changes, so progress bars
+    /// update in place. Lines past the output limit are read but dropped.
+    fn spawn_reader<R: Read + Send + 'static>(
+        mut reader: R,
+        output_tx: OutputSender,
+        stream: Stream,
+        counter: OutputCounter,
+    ) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            let mut buffer = LineBuffer::default();
+            let mut chunk = [0; 4096];
+            loop {
+                // A pty reports EIO once the command has exited, which ends
+                // the output just like end of file
+                let len = match reader.read(&mut chunk) {
+                    Ok(0) => break,
+                    Ok(len) => len,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => break,
+                };
+
+                let mut messages: Vec<ExecutionOutput> = buffer.push(&chunk[..len])
+                    .into_iter()
+                    .filter(|_| counter.count_line())
+                    .map(|line| ExecutionOutput::line(stream, line))
+                    .collect();
+                if let Some(partial) = buffer.take_partial().filter(|_| counter.within_limit()) {
+                    messages.push(ExecutionOutput::Partial(OutputLine::new(stream, partial)));
                 }
-            }
-        });
 
-        // Spawn a thread to read stderr
-        let stderr_thread = thread::spawn(move || {
-            for line in stderr_reader.lines() {
-                if let Ok(line) = line {
-                    if stderr_tx.send(ExecutionOutput::Stderr(line)).is_err() {
-                        break;
+                for message in messages {
+                    if output_tx.send(message).is_err() {
+                        return;
                     }
                 }
             }
-        });
 
-        // Wait for the command to finish or be terminated
-        let exit_status = loop {
-            // Check if we should terminate
-            if terminate_rx.try_recv().is_ok() {
-                // Kill the process
-                let _ = child.kill();
-                break None;
+            // Keep a last line without a newline, such as an unanswered prompt
+            if let Some(line) = buffer.finish().filter(|_| counter.count_line()) {
+                let _ = output_tx.send(ExecutionOutput::line(stream, line));
             }
+        })
+    }
 
-            // Check if the process has finished
-            match child.try_wait() {
-                Ok(Some(status)) => break Some(status),
-                Ok(None) => {
-                    // Process still running, sleep a bit
-                    thread::sleep(std::time::Duration::from_millis(10));
-                }
-                Err(_) => break None,
+    /// Create a channel for the output of a pipeline
+    fn output_channel(&self) -> (OutputSender, Receiver<ExecutionOutput>) {
+        let (tx, rx) = mpsc::channel();
+        (OutputSender { tx, notifier: self.notifier.clone() }, rx)
+    }
+
+    /// Check for new output from every job
+    pub fn check_output(&mut self) -> bool {
+        let mut job = std::mem::take(&mut self.foreground);
+        let mut updated = self.check_job(&mut job);
+        self.foreground = job;
+
+        let numbers: Vec<usize> = self.jobs.keys().copied().collect();
+        for number in numbers {
+            if let Some(mut job) = self.jobs.remove(&number) {
+                updated |= self.check_job(&mut job);
+                self.jobs.insert(number, job);
             }
+        }
+
+        updated | self.update_watch()
+    }
+
+    /// Run the watched command again once its last run is over and the next is due
+    fn update_watch(&mut self) -> bool {
+        if self.foreground.is_running() || self.foreground.fullscreen.is_some() {
+            return false;
+        }
+        let Some(watch) = &mut self.watch else {
+            return false;
         };
 
-        // Wait for the reader threads to finish
-        let _ = stdout_thread.join();
-        let _ = stderr_thread.join();
+        // Remember what the last run printed, below its header
+        if watch.is_running() {
+            let output = &self.foreground.result.output;
+            watch.finish_run(output.lines(1..output.len()).into_iter().map(|line| line.text));
+        }
+        if !watch.is_due(Instant::now()) {
+            return false;
+        }
 
-        // Send the finished message
-        let exit_code = exit_status.and_then(|s| s.code());
-        let _ = output_tx.send(ExecutionOutput::Finished(exit_code));
+        // Each run replaces the output pane, but not a background job shown there
+        let header = watch.start_run();
+        let command = watch.command.clone();
+        let selected = self.selected;
+        let mut job = self.start_foreground(&command);
+        self.selected = selected;
+        job.result.push_stdout(header);
+        self.run_line(job, &command);
+        true
+    }
 
-        Ok(())
+    /// Get when the watched command is next due to run, if at a set time
+    pub fn watch_deadline(&self) -> Option<Instant> {
+        self.watch.as_ref().and_then(Watch::deadline)
     }
 
-    /// Check for new output from the command
-    pub fn check_output(&mut self) -> bool {
-        let mut updated = false;
-        let mut finished = false;
-        let mut exit_code = None;
-
-        // Process all available output
-        if let Some(rx) = &self.output_rx {
-            // Check for new output
-            while let Ok(output) = rx.try_recv() {
-                match output {
-                    ExecutionOutput::Stdout(line) => {
-                        self.result.stdout.push(line);
-                        updated = true;
-                    }
-                    ExecutionOutput::Stderr(line) => {
-                        self.result.stderr.push(line);
-                        updated = true;
-                    }
-                    ExecutionOutput::Finished(code) => {
-                        exit_code = code;
-                        finished = true;
-                        updated = true;
-                    }
+    /// Stop watching a command, returning whether one was watched
+    pub fn stop_watch(&mut self) -> bool {
+        if self.watch.take().is_none() {
+            return false;
+        }
+        self.foreground.result.push_stdout("watch: stopped");
+        true
+    }
+
+    /// Flag which of the output lines starting at `start` weren't in the
+    /// watched command's previous run
+    ///
+    /// Empty unless the output pane shows a watched command.
+    pub fn watch_changes(&self, start: usize, lines: &[OutputLine]) -> Vec<bool> {
+        let Some(watch) = self.watch.as_ref().filter(|_| self.selected.is_none()) else {
+            return Vec::new();
+        };
+        lines.iter()
+            .enumerate()
+            .map(|(idx, line)| start + idx > 0 && watch.is_changed(&line.text))
+            .collect()
+    }
+
+    /// Collect a job's output, moving on through its command list when a
+    /// pipeline finishes
+    fn check_job(&mut self, job: &mut Job) -> bool {
+        let (updated, finished) = job.poll();
+
+        if let Some(completion) = finished {
+            // A line handed to the shell leaves the session where the shell
+            // ended up, unless it has gone to the background since
+            if let Some(shell) = job.shell.take() {
+                let (blank, state) = shell.finish();
+                if let Some(blank) = blank {
+                    job.result.push_line(blank, true);
+                }
+                if let Some(state) = state.filter(|_| !job.background) {
+                    self.apply_shell_state(state, &mut job.result);
                 }
             }
-        }
 
-        // Handle finished state after processing all output
-        if finished {
-            self.result.exit_code = exit_code;
-            self.output_rx = None;
-            self.terminate_tx = None;
+            let description = job.running.take().unwrap_or_default();
+            self.finish_pipeline(job, &description, completion);
+
+            // Ask for the password again when sudo wanted one it didn't get
+            let outcome = job.askpass.take().map(|askpass| askpass.outcome());
+            if let Some(AskpassOutcome::NoPassword | AskpassOutcome::WrongPassword) = outcome {
+                // The whole line runs again once the password is typed
+                job.pending.clear();
+                job.script.clear();
+                self.sudo_prompt = Some(SudoPrompt {
+                    command: job.command.clone(),
+                    wrong_password: outcome == Some(AskpassOutcome::WrongPassword),
+                });
+            }
+
+            // Move on to the rest of the command list
+            self.run_next(job);
         }
 
         updated
     }
 
-    /// Terminate the running command
+    /// Terminate every running job
     pub fn terminate(&mut self) {
-        if let Some(tx) = self.terminate_tx.take() {
-            let _ = tx.send(());
+        self.foreground.terminate();
+        for job in self.jobs.values_mut() {
+            job.terminate();
+        }
+    }
+
+    /// Interrupt the job shown in the output pane, like Ctrl+C in a shell
+    ///
+    /// Pressing it again escalates to SIGTERM and then SIGKILL for commands
+    /// that ignore the interrupt. Returns the signal sent, if anything runs.
+    pub fn interrupt(&mut self) -> Option<Signal> {
+        let signal = self.viewed_job().last_signal.map_or(Signal::Interrupt, Signal::escalate);
+        self.send_signal(signal)
+    }
+
+    /// Skip straight to the next signal after an interrupt
+    pub fn escalate(&mut self) -> Option<Signal> {
+        let signal = self.viewed_job().last_signal.unwrap_or(Signal::Interrupt).escalate();
+        self.send_signal(signal)
+    }
+
+    /// Send a signal to every stage of the pipeline the viewed job is running
+    fn send_signal(&mut self, signal: Signal) -> Option<Signal> {
+        let job = self.viewed_job_mut();
+        if !job.signal(signal) {
+            return None;
         }
 
-        self.output_rx = None;
+        // A signal ends the command list, like an interrupted shell line
+        job.pending.clear();
+        job.last_signal = Some(signal);
+        let hint = match signal {
+            Signal::Kill => String::new(),
+            _ => format!(", press Ctrl+C again to send {}", signal.escalate().name()),
+        };
+        job.result.push_stdout(format!("[{} sent{}]", signal.name(), hint));
+        Some(signal)
+    }
+
+    /// Send a line of input to the foreground job
+    pub fn send_line(&mut self, line: &str) {
+        self.foreground.send_line(line);
     }
 
-    /// Check if a command is currently running
+    /// Signal end of input to the foreground job, like Ctrl+D in a shell
+    pub fn send_eof(&mut self, pending: &str) {
+        self.foreground.send_eof(pending);
+    }
+
+    /// Check if the foreground job is running
     pub fn is_running(&self) -> bool {
-        self.output_rx.is_some()
+        self.foreground.is_running()
+    }
+
+    /// Check if any job, in the foreground or background, is running or stopped
+    pub fn has_jobs(&self) -> bool {
+        self.is_running() || self.jobs.values().any(|job| !matches!(job.state(), JobState::Done(_)))
+    }
+
+    /// List the background jobs for the jobs panel
+    pub fn jobs(&self) -> Vec<JobSummary> {
+        self.jobs.iter()
+            .map(|(&number, job)| JobSummary {
+                number,
+                command: job.command.clone(),
+                state: job.state(),
+                selected: self.selected == Some(number),
+            })
+            .collect()
+    }
+
+    /// Show a background job's output in the output pane, or the foreground
+    /// job's again when it is already shown
+    pub fn select_job(&mut self, number: usize) {
+        self.selected = if self.selected == Some(number) || !self.jobs.contains_key(&number) {
+            None
+        } else {
+            Some(number)
+        };
+    }
+
+    /// Get the number of the background job shown in the output pane, if any
+    pub fn selected_job(&self) -> Option<usize> {
+        self.selected.filter(|number| self.jobs.contains_key(number))
+    }
+
+    /// Get the job shown in the output pane
+    fn viewed_job(&self) -> &Job {
+        self.selected.and_then(|number| self.jobs.get(&number)).unwrap_or(&self.foreground)
+    }
+
+    /// Get the job shown in the output pane for changing it
+    fn viewed_job_mut(&mut self) -> &mut Job {
+        match self.selected_job() {
+            Some(number) => self.jobs.get_mut(&number).expect("selected job exists"),
+            None => &mut self.foreground,
+        }
+    }
+
+    /// Get the summary of the last external command run in the foreground
+    pub fn last_summary(&self) -> Option<&str> {
+        self.last_summary.as_deref()
     }
 
-    /// Get the current execution result
-    #[allow(dead_code)]
-    pub fn result(&self) -> &ExecutionResult {
-        &self.result
+    /// Get the corrections for the last foreground command if it wasn't found
+    pub fn suggestions(&self) -> Option<&Suggestions> {
+        self.suggestions.as_ref()
     }
 
-    /// Get all output lines (stdout and stderr combined)
-    pub fn all_output(&self) -> Vec<String> {
-        let mut output = Vec::new();
+    /// Get the aliases and functions of the session
+    pub fn aliases(&self) -> &Aliases {
+        &self.aliases
+    }
 
-        // Add stdout
-        output.extend(self.result.stdout.clone());
+    /// Get the directories saved by `pushd`, most recent first
+    pub fn dir_stack(&self) -> &[PathBuf] {
+        &self.dir_stack
+    }
 
-        // Add stderr
-        output.extend(self.result.stderr.clone());
+    /// Get the number of output lines of the job shown in the output pane
+    pub fn output_len(&self) -> usize {
+        self.viewed_job().result.output.len()
+    }
 
-        output
+    /// Get a range of output lines of the job shown in the output pane,
+    /// stdout and stderr in the order they arrived
+    pub fn output_lines(&self, range: Range<usize>) -> Vec<OutputLine> {
+        self.viewed_job().result.output.lines(range)
     }
 
     /// Run a command synchronously and return its output
@@ -444,15 +2340,9 @@ impl Executor {
         // Start the command
         let output = cmd.output()?;
 
-        // Process stdout
-        if let Ok(stdout) = String::from_utf8(output.stdout) {
-            result.stdout = stdout.lines().map(|s| s.to_string()).collect();
-        }
-
-        // Process stderr
-        if let Ok(stderr) = String::from_utf8(output.stderr) {
-            result.stderr = stderr.lines().map(|s| s.to_string()).collect();
-        }
+        // Process stdout, then stderr, as their order is lost here
+        String::from_utf8_lossy(&output.stdout).lines().for_each(|line| result.push_stdout(line));
+        String::from_utf8_lossy(&output.stderr).lines().for_each(|line| result.push_stderr(line));
 
         // Set exit code
         result.exit_code = output.status.code();
@@ -460,3 +2350,301 @@ impl Executor {
         Ok(result)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Tests that change or depend on the working directory take turns
+    static CWD: Mutex<()> = Mutex::new(());
+
+    /// Make an empty directory for a test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mouse_term-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    /// Run a command line to the end, returning its output without the
+    /// summary lines
+    fn run(executor: &mut Executor, command: &str) -> Vec<String> {
+        executor.execute(command).unwrap();
+        finish(executor, command)
+    }
+
+    /// Wait for the foreground job to end, returning its output without the
+    /// summary lines
+    fn finish(executor: &mut Executor, command: &str) -> Vec<String> {
+        let started = Instant::now();
+        loop {
+            executor.check_output();
+            if !executor.is_running() {
+                break;
+            }
+            assert!(started.elapsed() < Duration::from_secs(10), "{} didn't finish", command);
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        executor.output_lines(0..executor.output_len())
+            .into_iter()
+            .map(|line| line.text)
+            .filter(|text| !text.starts_with('['))
+            .collect()
+    }
+
+    #[test]
+    fn test_stack_index() {
+        let mut executor = Executor::new();
+        executor.dir_stack = vec![PathBuf::from("/a"), PathBuf::from("/b")];
+        let index = |arg| executor.stack_index(arg).map(|idx| idx.ok());
+
+        assert_eq!(index("+0"), Some(Some(0)));
+        assert_eq!(index("+2"), Some(Some(2)));
+        assert_eq!(index("-0"), Some(Some(2)));
+        assert_eq!(index("-2"), Some(Some(0)));
+        // Out of range is an error, not a directory name
+        assert_eq!(index("+3"), Some(None));
+        assert_eq!(index("-3"), Some(None));
+        assert_eq!(index("+x"), None);
+        assert_eq!(index("-"), None);
+        assert_eq!(index("src"), None);
+    }
+
+    #[test]
+    fn test_resolve_cdpath() {
+        let _cwd = CWD.lock().unwrap_or_else(|e| e.into_inner());
+        let base = temp_dir("cdpath");
+        fs::create_dir_all(base.join("projects/app")).unwrap();
+        std::env::set_current_dir(&base).unwrap();
+
+        // The empty entry at the end stands for the current directory
+        let mut executor = Executor::new();
+        executor.env.insert("CDPATH".to_string(), format!("{}:", base.join("projects").display()));
+        assert_eq!(executor.resolve_cdpath("app"), base.join("projects/app"));
+        assert_eq!(executor.resolve_cdpath("projects"), PathBuf::from("projects"));
+        assert_eq!(executor.resolve_cdpath("missing"), PathBuf::from("missing"));
+
+        // Explicit paths are never looked up
+        assert_eq!(executor.resolve_cdpath("./app"), PathBuf::from("./app"));
+        assert_eq!(executor.resolve_cdpath("../app"), PathBuf::from("../app"));
+        assert_eq!(executor.resolve_cdpath("/app"), PathBuf::from("/app"));
+    }
+
+    #[test]
+    fn test_cd_back() {
+        let _cwd = CWD.lock().unwrap_or_else(|e| e.into_inner());
+        let base = temp_dir("cd");
+        fs::create_dir(base.join("a")).unwrap();
+        fs::create_dir(base.join("b")).unwrap();
+        let mut executor = Executor::new();
+        let mut out = ExecutionResult::default();
+
+        for dir in [base.join("a"), base.join("b")] {
+            executor.handle_cd_command(&args(&[dir.to_str().unwrap()]), &mut out).unwrap();
+        }
+        assert_eq!(executor.handle_cd_command(&args(&["-"]), &mut out).unwrap(), 0);
+        assert_eq!(std::env::current_dir().unwrap(), base.join("a"));
+        assert_eq!(executor.env["OLDPWD"], base.join("b").to_string_lossy());
+    }
+
+    #[test]
+    fn test_pushd_popd() {
+        let _cwd = CWD.lock().unwrap_or_else(|e| e.into_inner());
+        let base = temp_dir("pushd");
+        let [a, b, c] = ["a", "b", "c"].map(|name| base.join(name));
+        for dir in [&a, &b, &c] {
+            fs::create_dir(dir).unwrap();
+        }
+        std::env::set_current_dir(&a).unwrap();
+
+        let mut executor = Executor::new();
+        let mut out = ExecutionResult::default();
+        // The stack by directory name, current directory first
+        let stack = |executor: &Executor| -> Vec<String> {
+            executor.full_dir_stack().unwrap()
+                .iter()
+                .map(|dir| dir.file_name().unwrap().to_string_lossy().to_string())
+                .collect()
+        };
+
+        executor.handle_pushd_command(&args(&[b.to_str().unwrap()]), &mut out).unwrap();
+        executor.handle_pushd_command(&args(&[c.to_str().unwrap()]), &mut out).unwrap();
+        assert_eq!(stack(&executor), ["c", "b", "a"]);
+
+        // On its own it swaps the top two, and +n and -n rotate
+        executor.handle_pushd_command(&[], &mut out).unwrap();
+        assert_eq!(stack(&executor), ["b", "c", "a"]);
+        executor.handle_pushd_command(&args(&["+2"]), &mut out).unwrap();
+        assert_eq!(stack(&executor), ["a", "b", "c"]);
+        executor.handle_pushd_command(&args(&["-0"]), &mut out).unwrap();
+        assert_eq!(stack(&executor), ["c", "a", "b"]);
+        assert!(executor.handle_pushd_command(&args(&["+3"]), &mut out).is_err());
+
+        // popd +n only removes, popd changes to the next directory
+        executor.handle_popd_command(&args(&["+1"]), &mut out).unwrap();
+        assert_eq!(stack(&executor), ["c", "b"]);
+        executor.handle_popd_command(&[], &mut out).unwrap();
+        assert_eq!(stack(&executor), ["b"]);
+        assert!(executor.handle_popd_command(&[], &mut out).is_err());
+    }
+
+    #[test]
+    fn test_source() {
+        let dir = temp_dir("source");
+        let file = dir.join("script.sh");
+        fs::write(&file, "# setup\necho one\necho 'unterminated\nfalse\n\necho two\n").unwrap();
+        let file = file.to_str().unwrap();
+        let mut executor = Executor::with_config(ExecutionConfig { pty: false, ..ExecutionConfig::default() });
+
+        // The file runs before the rest of the line, and its failures point
+        // back at the line they came from
+        let output = run(&mut executor, &format!("echo before; source {}; echo after", file));
+        assert_eq!(output.len(), 6, "{:?}", output);
+        assert_eq!(output[..2], ["before", "one"]);
+        assert!(output[2].starts_with(&format!("{}:3: Error: ", file)), "{:?}", output);
+        assert_eq!(output[3], format!("{}:4: false failed", file));
+        assert_eq!(output[4..], ["two", "after"]);
+
+        // A file that sources itself stops after MAX_SOURCED_FILES
+        let file = dir.join("loop.sh");
+        fs::write(&file, format!("echo x\nsource {}\n", file.display())).unwrap();
+        let output = run(&mut executor, &format!("source {}", file.display()));
+        assert_eq!(output.iter().filter(|text| *text == "x").count(), MAX_SOURCED_FILES);
+        assert!(output.contains(&format!("source: {}: too many files sourced", file.display())), "{:?}", output);
+    }
+
+    #[test]
+    fn test_source_file() {
+        let _cwd = CWD.lock().unwrap_or_else(|e| e.into_inner());
+        let start = std::env::current_dir().unwrap();
+        let dir = temp_dir("rc");
+        let rc = dir.join("startup");
+        fs::write(&rc, format!("alias hi='echo hi'\nexport RC_VAR=yes\ncd {}\ntrue\n", dir.display())).unwrap();
+        let mut executor = Executor::with_config(ExecutionConfig {
+            pty: false,
+            backend: Backend::Shell,
+            ..ExecutionConfig::default()
+        });
+
+        // Even with the shell backend, the rc file changes this session
+        executor.source_file(&rc);
+        let output = finish(&mut executor, "rc");
+        let cwd = std::env::current_dir().unwrap();
+        std::env::set_current_dir(&start).unwrap();
+        assert_eq!(output, [format!("Changed directory to: {}", dir.display()), "startup".to_string()]);
+        assert_eq!(cwd, dir);
+        assert_eq!(executor.aliases.expand_line("hi"), "echo hi");
+        assert_eq!(executor.env.get("RC_VAR").map(String::as_str), Some("yes"));
+
+        // It isn't what `!!` repeats, and `true` doesn't become the last summary
+        assert_eq!(executor.last_command, None);
+        assert_eq!(executor.last_summary, None);
+    }
+
+    #[test]
+    fn test_is_fullscreen_command() {
+        let mut executor = Executor::with_config(ExecutionConfig { pty: false, ..ExecutionConfig::default() });
+        run(&mut executor, "alias v=vim");
+        run(&mut executor, "alias t='sudo -u root htop'");
+
+        assert!(executor.is_fullscreen_command("vim notes.txt"));
+        assert!(executor.is_fullscreen_command("sudo -E nano notes.txt"));
+        // Aliases are looked through, like when the line runs
+        assert!(executor.is_fullscreen_command("v notes.txt"));
+        assert!(executor.is_fullscreen_command("cd /tmp && t"));
+        assert!(!executor.is_fullscreen_command("v notes.txt > out"));
+        assert!(!executor.is_fullscreen_command("ls -l"));
+    }
+
+    #[test]
+    fn test_export_listing() {
+        let mut executor = Executor::with_config(ExecutionConfig { pty: false, ..ExecutionConfig::default() });
+        executor.env.insert("MT_PLAIN".to_string(), "/usr/bin".to_string());
+        executor.env.insert("MT_EMPTY".to_string(), String::new());
+        executor.env.insert("MT_QUOTED".to_string(), "say \"$HOME\" `it's`\nnow".to_string());
+
+        let output: Vec<String> = run(&mut executor, "export")
+            .into_iter()
+            .filter(|line| line.starts_with("export MT_"))
+            .collect();
+        assert_eq!(output, [
+            "export MT_EMPTY=''",
+            "export MT_PLAIN=/usr/bin",
+            "export MT_QUOTED='say \"$HOME\" `it'\\''s`\nnow'",
+        ]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_kill_jobs_and_pids() {
+        let mut executor = Executor::with_config(ExecutionConfig { pty: false, ..ExecutionConfig::default() });
+        run(&mut executor, "sleep 30 &");
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+
+        // Job specs and pids are both signalled
+        let output = run(&mut executor, &format!("kill -TERM %1 {}", child.id()));
+        assert!(output.is_empty(), "{:?}", output);
+        assert_eq!(process::exit_signal(&child.wait().unwrap()), Some(libc::SIGTERM));
+        let started = Instant::now();
+        while executor.jobs.get(&1).is_some_and(|job| job.state() == JobState::Running) {
+            assert!(started.elapsed() < Duration::from_secs(10), "job 1 didn't end");
+            executor.check_output();
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        // Bad targets are reported without stopping the rest
+        let output = run(&mut executor, "kill %9 nope");
+        assert_eq!(output, ["kill: %9: no such job", "kill: nope: arguments must be process or job IDs"]);
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::ZERO), "0.00s");
+        assert_eq!(format_duration(Duration::from_millis(420)), "0.42s");
+        assert_eq!(format_duration(Duration::from_micros(1_995)), "0.00s");
+        assert_eq!(format_duration(Duration::from_micros(1_996_000)), "2.00s");
+        assert_eq!(format_duration(Duration::from_millis(59_994)), "59.99s");
+        assert_eq!(format_duration(Duration::from_millis(59_996)), "1m00s");
+        assert_eq!(format_duration(Duration::from_millis(187_400)), "3m07s");
+        assert_eq!(format_duration(Duration::from_millis(119_600)), "2m00s");
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 KB");
+        assert_eq!(format_size(812), "812 KB");
+        assert_eq!(format_size(1024), "1.0 MB");
+        assert_eq!(format_size(46_285), "45.2 MB");
+        assert_eq!(format_size(1024 * 1024 - 1), "1.0 GB");
+        assert_eq!(format_size(3 * 1024 * 1024), "3.0 GB");
+    }
+
+    #[test]
+    fn test_summary() {
+        let usage = ResourceUsage {
+            wall: Duration::from_millis(1_234),
+            user: Duration::from_millis(800),
+            system: Duration::from_millis(50),
+            max_rss_kb: 2_150,
+        };
+        let result = |exit_code, signal, usage| ExecutionResult { exit_code, signal, usage, ..ExecutionResult::default() };
+
+        assert_eq!(
+            result(Some(0), None, Some(usage)).summary(),
+            "exit 0 · 1.23s · user 0.80s · sys 0.05s · max RSS 2.1 MB",
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            result(None, Some(libc::SIGKILL), Some(usage)).summary(),
+            "killed by SIGKILL · 1.23s · user 0.80s · sys 0.05s · max RSS 2.1 MB",
+        );
+        assert_eq!(result(Some(127), None, None).summary(), "exit 127");
+        assert_eq!(result(None, None, None).summary(), "terminated");
+    }
+}
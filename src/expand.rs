@@ -0,0 +1,332 @@
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::Chars;
+
+/// Values available when expanding words before a command runs
+pub struct ExpandContext<'a> {
+    /// The session environment
+    pub env: &'a HashMap<String, String>,
+    /// Exit code of the last command, for `$?`
+    pub last_exit_code: Option<i32>,
+}
+
+impl ExpandContext<'_> {
+    /// Look up the value of a variable, treating unset variables as empty
+    fn lookup(&self, name: &str) -> String {
+        match name {
+            "?" => self.last_exit_code.unwrap_or(0).to_string(),
+            "$" => std::process::id().to_string(),
+            _ => self.env.get(name).cloned().unwrap_or_default(),
+        }
+    }
+
+    /// Get the home directory used for tilde expansion
+    fn home(&self) -> Option<String> {
+        self.env.get("HOME").cloned().or_else(|| {
+            dirs::home_dir().map(|home| home.to_string_lossy().to_string())
+        })
+    }
+}
+
+/// A character of a word after quote removal
+#[derive(Debug, Clone, Copy)]
+struct WordChar {
+    c: char,
+    /// Quoted characters are never treated as glob or brace syntax
+    quoted: bool,
+}
+
+/// Expand a word into the arguments it stands for
+///
+/// Runs tilde and variable expansion, removes quotes and escapes, then
+/// expands brace lists and globs on whatever was left unquoted. An unquoted
+/// word that expands to nothing disappears, while `""` stays an empty argument.
+pub fn expand_word(word: &str, ctx: &ExpandContext) -> Vec<String> {
+    expand_word_limited(word, ctx, usize::MAX)
+}
+
+/// Expand a word, stopping after `limit` glob results
+///
+/// Used for previews, where walking a huge `**` tree would stall the UI.
+pub fn expand_word_limited(word: &str, ctx: &ExpandContext, limit: usize) -> Vec<String> {
+    let (chars, had_quotes) = unquote(word, Some(ctx));
+    if chars.is_empty() {
+        return if had_quotes { vec![String::new()] } else { Vec::new() };
+    }
+
+    let mut expanded = Vec::new();
+    for alternative in expand_braces(&chars) {
+        if expanded.len() >= limit {
+            break;
+        }
+        expanded.append(&mut expand_glob(&alternative, limit - expanded.len()));
+    }
+    expanded
+}
+
+/// Expand a word that names a single path, such as a redirect target
+///
+/// Only tilde and variable expansion and quote removal apply.
+pub fn expand_path(word: &str, ctx: &ExpandContext) -> String {
+    unquote(word, Some(ctx)).0.iter().map(|wc| wc.c).collect()
+}
+
+/// Check whether a word contains unquoted glob characters or a brace list
+pub fn is_glob_pattern(word: &str) -> bool {
+    let (chars, _) = unquote(word, None);
+    chars.iter().any(is_glob_char) || expand_braces(&chars).len() > 1
+}
+
+/// Show a path with the home directory written as `~`, the way `dirs` does
+pub fn collapse_home(path: &Path, home: Option<&Path>) -> String {
+    match home.and_then(|home| path.strip_prefix(home).ok()) {
+        Some(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+        Some(rest) => format!("~/{}", rest.display()),
+        None => path.display().to_string(),
+    }
+}
+
+/// Remove quotes and escapes from a word, noting which characters were quoted
+///
+/// With a context, a leading tilde and variable references are expanded
+/// too, except inside single quotes. Returns whether the word had any quotes.
+fn unquote(word: &str, ctx: Option<&ExpandContext>) -> (Vec<WordChar>, bool) {
+    let mut unquoted = Vec::new();
+    let mut had_quotes = false;
+    let mut quote: Option<char> = None;
+    let mut chars = word.chars().peekable();
+
+    // A leading `~` refers to the home directory
+    if let Some(ctx) = ctx
+        && (word == "~" || word.starts_with("~/"))
+        && let Some(home) = ctx.home()
+    {
+        chars.next();
+        unquoted.extend(home.chars().map(|c| WordChar { c, quoted: true }));
+    }
+
+    while let Some(c) = chars.next() {
+        let quoted = quote.is_some();
+        match (quote, c) {
+            (Some(open), c) if c == open => quote = None,
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                had_quotes = true;
+            }
+            // Backslash escapes the next character, inside double quotes only
+            // where that character would otherwise be special
+            (None, '\\') => match chars.next() {
+                Some(next) => unquoted.push(WordChar { c: next, quoted: true }),
+                None => unquoted.push(WordChar { c, quoted: false }),
+            },
+            (Some('"'), '\\') => match chars.next_if(|next| matches!(next, '"' | '\\' | '$' | '`')) {
+                Some(next) => unquoted.push(WordChar { c: next, quoted: true }),
+                None => unquoted.push(WordChar { c, quoted: true }),
+            },
+            (None | Some('"'), '$') if let Some(ctx) = ctx => match parse_variable(&mut chars) {
+                Some(name) => unquoted.extend(ctx.lookup(&name).chars().map(|c| WordChar { c, quoted })),
+                None => unquoted.push(WordChar { c, quoted }),
+            },
+            _ => unquoted.push(WordChar { c, quoted }),
+        }
+    }
+
+    (unquoted, had_quotes)
+}
+
+/// Parse the name of a variable reference following a `$`
+///
+/// Returns `None`, consuming nothing, when the `$` does not start a reference.
+fn parse_variable(chars: &mut Peekable<Chars>) -> Option<String> {
+    match chars.peek() {
+        // ${NAME}
+        Some('{') => {
+            let mut lookahead = chars.clone();
+            lookahead.next();
+            let mut name = String::new();
+            for c in lookahead.by_ref() {
+                if c == '}' {
+                    if is_valid_name(&name) || name == "?" {
+                        *chars = lookahead;
+                        return Some(name);
+                    }
+                    break;
+                }
+                name.push(c);
+            }
+            None
+        }
+        // $? and $$
+        Some(&special @ ('?' | '$')) => {
+            chars.next();
+            Some(special.to_string())
+        }
+        // $NAME
+        Some(&next) if next == '_' || next.is_ascii_alphabetic() => {
+            let mut name = String::new();
+            while let Some(c) = chars.next_if(|&c| c == '_' || c.is_ascii_alphanumeric()) {
+                name.push(c);
+            }
+            Some(name)
+        }
+        // A lone `$` is kept as is
+        _ => None,
+    }
+}
+
+/// Check whether a character is unquoted glob syntax
+fn is_glob_char(wc: &WordChar) -> bool {
+    !wc.quoted && matches!(wc.c, '*' | '?' | '[')
+}
+
+/// Match a word against the filesystem if it contains unquoted glob syntax
+///
+/// Patterns without any match are kept literally, as in a shell.
+fn expand_glob(word: &[WordChar], limit: usize) -> Vec<String> {
+    let literal: String = word.iter().map(|wc| wc.c).collect();
+    if !word.iter().any(is_glob_char) {
+        return vec![literal];
+    }
+
+    // Quoted characters must match themselves
+    let pattern: String = word.iter()
+        .map(|wc| if wc.quoted { glob::Pattern::escape(&wc.c.to_string()) } else { wc.c.to_string() })
+        .collect();
+
+    let options = glob::MatchOptions {
+        case_sensitive: true,
+        require_literal_separator: true,
+        require_literal_leading_dot: true,
+    };
+    let mut matches: Vec<String> = match glob::glob_with(&pattern, options) {
+        Ok(paths) => paths
+            .filter_map(|path| path.ok())
+            .take(limit)
+            .map(|path| path.to_string_lossy().to_string())
+            .collect(),
+        // An invalid pattern is just an ordinary word
+        Err(_) => Vec::new(),
+    };
+
+    if matches.is_empty() {
+        vec![literal]
+    } else {
+        matches.sort();
+        matches
+    }
+}
+
+/// Expand brace lists such as `file.{rs,toml}` into each alternative
+///
+/// Braces without an unquoted comma at the top level are kept literally.
+fn expand_braces(word: &[WordChar]) -> Vec<Vec<WordChar>> {
+    let is = |wc: &WordChar, c: char| !wc.quoted && wc.c == c;
+
+    // Find the first brace group with a top-level comma
+    for start in (0..word.len()).filter(|&i| is(&word[i], '{')) {
+        let mut depth = 0;
+        let mut commas = Vec::new();
+        let mut end = None;
+
+        for (i, wc) in word.iter().enumerate().skip(start) {
+            if is(wc, '{') {
+                depth += 1;
+            } else if is(wc, '}') {
+                depth -= 1;
+                if depth == 0 {
+                    end = Some(i);
+                    break;
+                }
+            } else if is(wc, ',') && depth == 1 {
+                commas.push(i);
+            }
+        }
+
+        let Some(end) = end else { break };
+        if commas.is_empty() {
+            continue;
+        }
+
+        let mut bounds = vec![start];
+        bounds.extend(&commas);
+        bounds.push(end);
+
+        // Expand each alternative, then any further groups in the result
+        return bounds.windows(2)
+            .flat_map(|pair| {
+                let alternative = [&word[..start], &word[pair[0] + 1..pair[1]], &word[end + 1..]].concat();
+                expand_braces(&alternative)
+            })
+            .collect();
+    }
+
+    vec![word.to_vec()]
+}
+
+/// Check whether a string is a valid environment variable name
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c == '_' || c.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_variables() {
+        let env = HashMap::from([
+            ("HOME".to_string(), "/home/me".to_string()),
+            ("NAME".to_string(), "world".to_string()),
+        ]);
+        let ctx = ExpandContext { env: &env, last_exit_code: Some(3) };
+
+        assert_eq!(expand_word("~/src", &ctx), ["/home/me/src"]);
+        assert_eq!(expand_word("a~b", &ctx), ["a~b"]);
+        assert_eq!(expand_word("hello-$NAME!", &ctx), ["hello-world!"]);
+        assert_eq!(expand_word("${NAME}s", &ctx), ["worlds"]);
+        assert_eq!(expand_word("status=$?", &ctx), ["status=3"]);
+        assert_eq!(expand_word("$MISSING", &ctx), Vec::<String>::new());
+        assert_eq!(expand_word("5$", &ctx), ["5$"]);
+        assert_eq!(expand_word("${NAME", &ctx), ["${NAME"]);
+    }
+
+    #[test]
+    fn test_collapse_home() {
+        let home = Some(Path::new("/home/me"));
+        assert_eq!(collapse_home(Path::new("/home/me/src"), home), "~/src");
+        assert_eq!(collapse_home(Path::new("/home/me"), home), "~");
+        assert_eq!(collapse_home(Path::new("/home/meg"), home), "/home/meg");
+        assert_eq!(collapse_home(Path::new("/home/me/src"), None), "/home/me/src");
+    }
+
+    #[test]
+    fn test_expand_quotes() {
+        let env = HashMap::from([("NAME".to_string(), "world".to_string())]);
+        let ctx = ExpandContext { env: &env, last_exit_code: None };
+
+        assert_eq!(expand_word("\"fix bug\"", &ctx), ["fix bug"]);
+        assert_eq!(expand_word("\"hi $NAME\"", &ctx), ["hi world"]);
+        assert_eq!(expand_word("'hi $NAME'", &ctx), ["hi $NAME"]);
+        assert_eq!(expand_word(r#"a\ b\"c"#, &ctx), ["a b\"c"]);
+        assert_eq!(expand_word(r#""\$x \n""#, &ctx), ["$x \\n"]);
+        assert_eq!(expand_word("\"\"", &ctx), [""]);
+        assert_eq!(expand_word("\"~\"/*.nothing", &ctx), ["~/*.nothing"]);
+        assert!(!is_glob_pattern("\"*.rs\""));
+        assert!(is_glob_pattern("'src'/*.rs"));
+    }
+
+    #[test]
+    fn test_expand_braces() {
+        let env = HashMap::new();
+        let ctx = ExpandContext { env: &env, last_exit_code: None };
+
+        assert_eq!(expand_word("src/{main,lib}.rs", &ctx), ["src/main.rs", "src/lib.rs"]);
+        assert_eq!(expand_word("{a,b{1,2}}c", &ctx), ["ac", "b1c", "b2c"]);
+        assert_eq!(expand_word("x{}y{z}", &ctx), ["x{}y{z}"]);
+        assert_eq!(expand_word("{unclosed,", &ctx), ["{unclosed,"]);
+        assert_eq!(expand_word("\"{a,b}\"", &ctx), ["{a,b}"]);
+    }
+}
@@ -54,7 +54,7 @@ impl History {
     /// Add a command to the history
     pub fn add(&mut self, command: String) {
         // Don't add empty commands or duplicates of the most recent command
-        if command.trim().is_empty() || self.commands.front().map_or(false, |c| c == &command) {
+        if command.trim().is_empty() || self.commands.front() == Some(&command) {
             return;
         }
 
@@ -254,12 +254,10 @@ impl History {
         }
 
         let mut backups = Vec::new();
-        for entry in fs::read_dir(backup_dir)? {
-            if let Ok(entry) = entry {
-                let path = entry.path();
-                if path.is_file() && path.extension().map_or(false, |ext| ext == "json") {
-                    backups.push(path);
-                }
+        for entry in fs::read_dir(backup_dir)?.flatten() {
+            let path = entry.path();
+            if path.is_file() && path.extension().is_some_and(|ext| ext == "json") {
+                backups.push(path);
             }
         }
 
@@ -2,7 +2,7 @@ use anyhow::Result;
 use thiserror::Error;
 
 /// Errors that can occur during input processing
-#[derive(Error, Debug)]
+#[derive(Error, Debug, PartialEq)]
 pub enum InputError {
     #[error("Invalid token index: {0}")]
     InvalidTokenIndex(usize),
@@ -11,17 +11,32 @@ pub enum InputError {
     UnmatchedQuote,
 }
 
+/// Shell operators, longest first so that `&&` wins over `&`
+const OPERATORS: &[&str] = &["2>&1", "&>>", "2>>", "&&", "||", ">>", "&>", "2>", "|", ";", "&", "<", ">"];
+
+/// The kind of a token in the command line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind {
+    /// A word, with its quotes and escapes still in place
+    Word,
+    /// A shell operator such as `|`, `&&` or `>`
+    Operator,
+}
+
 /// Represents a token in the command line
 #[derive(Debug, Clone)]
 pub struct Token {
-    /// The text content of the token
+    /// The text content of the token, exactly as typed
     pub text: String,
+    /// Whether the token is a word or an operator
+    pub kind: TokenKind,
     /// The byte range in the original input string
     #[allow(dead_code)]
     pub range: (usize, usize),
 }
 
 /// State for the input line and editor
+#[derive(Default)]
 pub struct InputState {
     /// The raw input string
     pub raw_input: String,
@@ -31,16 +46,6 @@ pub struct InputState {
     pub editing: Option<String>,
 }
 
-impl Default for InputState {
-    fn default() -> Self {
-        Self {
-            raw_input: String::new(),
-            tokens: Vec::new(),
-            editing: None,
-        }
-    }
-}
-
 impl InputState {
     /// Create a new empty input state
     pub fn new() -> Self {
@@ -83,6 +88,7 @@ impl InputState {
 
             // Rebuild the raw input from tokens
             self.rebuild_raw_input();
+            self.tokenize()?;
         }
 
         Ok(())
@@ -103,9 +109,13 @@ impl InputState {
         self.raw_input = self.tokens
             .iter()
             .map(|token| {
-                // Quote the token if it contains whitespace
-                if token.text.contains(char::is_whitespace) {
-                    format!("\"{}\"", token.text)
+                // Quote a word that would no longer come back as a single token
+                let single_word = matches!(
+                    tokenize(&token.text).as_deref(),
+                    Ok([only]) if only.kind == TokenKind::Word && only.text == token.text
+                );
+                if token.kind == TokenKind::Word && !single_word {
+                    quote(&token.text)
                 } else {
                     token.text.clone()
                 }
@@ -115,71 +125,102 @@ impl InputState {
     }
 
     /// Tokenize the raw input into tokens
+    ///
+    /// An unterminated quote is not an error here, it just runs to the end of
+    /// the line while the user is still typing.
     fn tokenize(&mut self) -> Result<()> {
-        self.tokens.clear();
+        self.tokens = scan(&self.raw_input).0;
+        Ok(())
+    }
 
-        let mut tokens = Vec::new();
-        let mut current_token = String::new();
-        let mut in_quotes = false;
-        let mut escaped = false;
-        let mut start_pos = 0;
-
-        for (i, c) in self.raw_input.char_indices() {
-            if escaped {
-                current_token.push(c);
-                escaped = false;
-                continue;
-            }
+    /// Get the full command string
+    pub fn get_command(&self) -> String {
+        self.raw_input.clone()
+    }
+}
 
-            match c {
-                '\\' => {
-                    escaped = true;
-                }
-                '"' => {
-                    in_quotes = !in_quotes;
-                }
-                ' ' | '\t' if !in_quotes => {
-                    if !current_token.is_empty() {
-                        tokens.push(Token {
-                            text: current_token,
-                            range: (start_pos, i),
-                        });
-                        current_token = String::new();
-                        start_pos = i + 1;
-                    } else {
-                        // Skip consecutive whitespace
-                        start_pos = i + 1;
-                    }
-                }
-                _ => {
-                    current_token.push(c);
-                }
+/// Split a command line into word and operator tokens
+///
+/// Double quotes, single quotes and backslash escapes keep whitespace and
+/// operators inside a word. Token text is left as typed, quote removal
+/// happens when the word is expanded.
+pub fn tokenize(input: &str) -> Result<Vec<Token>, InputError> {
+    match scan(input) {
+        (_, true) => Err(InputError::UnmatchedQuote),
+        (tokens, false) => Ok(tokens),
+    }
+}
+
+/// Scan a command line into tokens, reporting whether a quote was left open
+fn scan(input: &str) -> (Vec<Token>, bool) {
+    let mut tokens = Vec::new();
+    let mut quote: Option<char> = None;
+    let mut word_start: Option<usize> = None;
+    let mut i = 0;
+
+    while let Some(c) = input[i..].chars().next() {
+        if let Some(open) = quote {
+            if c == open {
+                quote = None;
+            } else if c == '\\' && open == '"' {
+                // Skip the escaped character so `\"` does not close the quote
+                i += input[i + 1..].chars().next().map_or(0, char::len_utf8);
             }
+            i += c.len_utf8();
+            continue;
         }
 
-        // Check for unmatched quotes
-        if in_quotes {
-            return Err(InputError::UnmatchedQuote.into());
+        // Operators end the current word, except `2>` which must start one
+        let operator = OPERATORS.iter().find(|op| {
+            input[i..].starts_with(**op) && (!op.starts_with('2') || word_start.is_none())
+        });
+        if c.is_whitespace() || operator.is_some() {
+            if let Some(start) = word_start.take() {
+                tokens.push(Token { text: input[start..i].to_string(), kind: TokenKind::Word, range: (start, i) });
+            }
+            if let Some(op) = operator {
+                tokens.push(Token { text: op.to_string(), kind: TokenKind::Operator, range: (i, i + op.len()) });
+                i += op.len();
+            } else {
+                i += c.len_utf8();
+            }
+            continue;
         }
 
-        // Add the last token if there is one
-        if !current_token.is_empty() {
-            tokens.push(Token {
-                text: current_token,
-                range: (start_pos, self.raw_input.len()),
-            });
+        word_start.get_or_insert(i);
+        match c {
+            '"' | '\'' => quote = Some(c),
+            '\\' => i += input[i + 1..].chars().next().map_or(0, char::len_utf8),
+            _ => {}
         }
+        i += c.len_utf8();
+    }
 
-        self.tokens = tokens;
-        Ok(())
+    // Add the last word if there is one
+    if let Some(start) = word_start {
+        tokens.push(Token { text: input[start..].to_string(), kind: TokenKind::Word, range: (start, input.len()) });
     }
 
-    /// Get the full command string
-    pub fn get_command(&self) -> String {
-        self.raw_input.clone()
+    (tokens, quote.is_some())
+}
+
+/// Quote text so it is passed to a program exactly as given
+///
+/// Words made of ordinary characters are left as they are.
+pub fn quote_literal(text: &str) -> String {
+    let plain = |c: char| c.is_alphanumeric() || "_-./+,:@%=".contains(c);
+    if !text.is_empty() && text.chars().all(plain) {
+        text.to_string()
+    } else {
+        format!("'{}'", text.replace('\'', "'\\''"))
     }
 }
 
+/// Quote a word so it is read back as a single token
+fn quote(word: &str) -> String {
+    format!("\"{}\"", word.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -205,4 +246,27 @@ mod tests {
         assert_eq!(input_state.tokens[1].text, "\"hello world\"");
         assert_eq!(input_state.tokens[2].text, "test");
     }
+
+    #[test]
+    fn test_tokenize_operators() {
+        let tokens = tokenize("cat 'a b'|grep \"x;y\" 2>&1 >out\\ file&&echo 2>x").unwrap();
+        let texts: Vec<&str> = tokens.iter().map(|t| t.text.as_str()).collect();
+        assert_eq!(texts, ["cat", "'a b'", "|", "grep", "\"x;y\"", "2>&1", ">", "out\\ file", "&&", "echo", "2>", "x"]);
+        assert_eq!(tokens[2].kind, TokenKind::Operator);
+        assert_eq!(tokens[4].kind, TokenKind::Word);
+
+        assert_eq!(tokenize("echo \"open").unwrap_err(), InputError::UnmatchedQuote);
+    }
+
+    #[test]
+    fn test_edit_requotes_token() {
+        let mut input_state = InputState::new();
+        input_state.set_input("echo hi".to_string()).unwrap();
+        input_state.start_editing(1).unwrap();
+        input_state.update_editing("hello world".to_string());
+        input_state.commit_edit(1).unwrap();
+
+        assert_eq!(input_state.raw_input, "echo \"hello world\"");
+        assert_eq!(input_state.tokens.len(), 2);
+    }
 }
@@ -0,0 +1,262 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::mpsc::{Receiver, Sender};
+
+use crate::executor::{Completion, ExecutionOutput, ExecutionResult, PipelineEvent};
+use crate::parser::{ListItem, Pipeline};
+use crate::process::Signal;
+use crate::shell::ShellCapture;
+use crate::sudo::{Askpass, Secret};
+
+/// What a job is doing, as shown by `jobs` and the jobs panel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JobState {
+    /// A pipeline of the job is running
+    Running,
+    /// The job was stopped, with Ctrl+Z or `kill -STOP`
+    Stopped,
+    /// The whole command list has finished with this exit code
+    Done(Option<i32>),
+}
+
+impl fmt::Display for JobState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobState::Running => write!(f, "Running"),
+            JobState::Stopped => write!(f, "Stopped"),
+            JobState::Done(Some(0)) => write!(f, "Done"),
+            JobState::Done(Some(code)) => write!(f, "Exit {}", code),
+            JobState::Done(None) => write!(f, "Terminated"),
+        }
+    }
+}
+
+/// A command line run by the executor, in the foreground or as a background job
+#[derive(Default)]
+pub struct Job {
+    /// Job number, assigned once the job is first sent to the background
+    pub number: Option<usize>,
+    /// The command line as typed
+    pub command: String,
+    /// Output and exit code of the job so far
+    pub result: ExecutionResult,
+    /// Whether the job runs in the background
+    pub background: bool,
+    /// Whether the job is stopped
+    pub stopped: bool,
+    /// Whether the job was listed as done, so it can be removed
+    pub reported: bool,
+    /// Pipelines of the command list still waiting to run
+    pub pending: VecDeque<ListItem>,
+    /// Sourced files still waiting to run once `pending` is done
+    pub script: VecDeque<Script>,
+    /// How many files `source` has read for this job
+    pub sourced: usize,
+    /// Where the running line of a sourced file is, as `file:line`
+    pub script_line: Option<String>,
+    /// The pipeline currently running, as typed
+    pub running: Option<String>,
+    /// Whether to report the exit code of each pipeline in the list
+    pub report_status: bool,
+    /// Whether the job runs the rc file, which leaves the last summary alone
+    pub startup: bool,
+    /// Channel for receiving output of the running pipeline
+    pub output_rx: Option<Receiver<ExecutionOutput>>,
+    /// Channel for sending signals to the thread running the pipeline
+    pub signal_tx: Option<Sender<PipelineEvent>>,
+    /// Master side of the pty the running pipeline is attached to
+    pub pty_master: Option<File>,
+    /// Where input for the running pipeline is written
+    pub stdin: Option<Box<dyn Write + Send>>,
+    /// A full-screen command waiting for the UI to hand over the terminal
+    pub fullscreen: Option<Pipeline>,
+    /// The last signal sent to the running pipeline, for escalation
+    pub last_signal: Option<Signal>,
+    /// The password typed for the job's first sudo command
+    pub sudo_password: Option<Secret>,
+    /// Gives the password to the running sudo command
+    pub askpass: Option<Askpass>,
+    /// Picks out what the shell reports, when the line was handed to the shell
+    pub shell: Option<ShellCapture>,
+}
+
+/// Work queued by `source`
+#[derive(Debug, Clone)]
+pub enum Script {
+    /// A line of a sourced file, parsed when its turn comes
+    Line {
+        /// The file, as given to `source`
+        file: String,
+        /// Line number in the file, starting at 1
+        number: usize,
+        /// The command line
+        text: String,
+    },
+    /// The rest of the command list that ran `source`
+    Rest(VecDeque<ListItem>),
+}
+
+/// A line of the jobs panel
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobSummary {
+    /// Job number
+    pub number: usize,
+    /// The command line as typed
+    pub command: String,
+    /// What the job is doing
+    pub state: JobState,
+    /// Whether the output pane shows this job
+    pub selected: bool,
+}
+
+impl Job {
+    /// Create a job for a command line
+    pub fn new(command: &str) -> Self {
+        Self {
+            command: command.to_string(),
+            ..Self::default()
+        }
+    }
+
+    /// Check if a pipeline of the job is running
+    pub fn is_running(&self) -> bool {
+        self.output_rx.is_some()
+    }
+
+    /// Get what the job is doing
+    pub fn state(&self) -> JobState {
+        if self.stopped {
+            JobState::Stopped
+        } else if self.is_running() || self.fullscreen.is_some() {
+            JobState::Running
+        } else {
+            JobState::Done(self.result.exit_code)
+        }
+    }
+
+    /// Collect output from the running pipeline
+    ///
+    /// Returns whether anything arrived, and how the pipeline ended once it
+    /// has finished.
+    pub fn poll(&mut self) -> (bool, Option<Completion>) {
+        let mut updated = false;
+        let mut finished = None;
+
+        if let Some(rx) = &self.output_rx {
+            while let Ok(output) = rx.try_recv() {
+                let lines = match (output, &mut self.shell) {
+                    // Leave out what the shell reports after the command
+                    (ExecutionOutput::Line(line), Some(shell)) => shell.line(line),
+                    (ExecutionOutput::Partial(line), Some(shell)) => shell.partial(line),
+                    (ExecutionOutput::Line(line), None) => vec![(line, true)],
+                    (ExecutionOutput::Partial(line), None) => vec![(line, false)],
+                    (ExecutionOutput::Finished(completion), _) => {
+                        finished = Some(completion);
+                        Vec::new()
+                    }
+                };
+                for (line, complete) in lines {
+                    self.result.push_line(line, complete);
+                }
+                updated = true;
+            }
+        }
+
+        if finished.is_some() {
+            self.output_rx = None;
+            self.signal_tx = None;
+            self.pty_master = None;
+            self.stdin = None;
+            self.stopped = false;
+        }
+
+        (updated, finished)
+    }
+
+    /// Send a signal to every stage of the running pipeline
+    ///
+    /// Returns false when nothing is running.
+    pub fn signal(&mut self, signal: Signal) -> bool {
+        let sent = self.signal_tx.as_ref().is_some_and(|tx| tx.send(PipelineEvent::Signal(signal)).is_ok());
+        if sent {
+            match signal {
+                Signal::Suspend | Signal::Stop => self.stopped = true,
+                Signal::Continue => self.stopped = false,
+                _ => {}
+            }
+        }
+        sent
+    }
+
+    /// Kill the running pipeline and drop the rest of the command list
+    pub fn terminate(&mut self) {
+        self.pending.clear();
+        self.script.clear();
+        self.running = None;
+        self.fullscreen = None;
+        self.shell = None;
+
+        if let Some(tx) = self.signal_tx.take() {
+            let _ = tx.send(PipelineEvent::Signal(Signal::Kill));
+        }
+
+        self.output_rx = None;
+        self.pty_master = None;
+        self.stdin = None;
+        self.stopped = false;
+    }
+
+    /// Send a line of input to the running pipeline
+    ///
+    /// A pty echoes input back itself; with plain pipes the line is echoed
+    /// into the output here so the transcript shows what was typed.
+    pub fn send_line(&mut self, line: &str) {
+        // A terminal sends carriage return for Enter
+        let terminator = if self.pty_master.is_some() { "\r" } else { "\n" };
+        if self.pty_master.is_none() {
+            self.result.push_stdout(line);
+        }
+
+        self.write_stdin(format!("{}{}", line, terminator).as_bytes());
+    }
+
+    /// Signal end of input to the running pipeline, like Ctrl+D in a shell
+    ///
+    /// Any text typed so far is sent first without a line terminator.
+    pub fn send_eof(&mut self, pending: &str) {
+        if self.pty_master.is_some() {
+            // The terminal turns EOT into end of file for the reader, but
+            // the first one only flushes a partially typed line
+            let mut data = pending.as_bytes().to_vec();
+            if !pending.is_empty() {
+                data.push(0x04);
+            }
+            data.push(0x04);
+            self.write_stdin(&data);
+        } else {
+            if !pending.is_empty() {
+                self.result.push_stdout(pending);
+                self.write_stdin(pending.as_bytes());
+            }
+            // Closing the pipe is the end of file
+            self.stdin = None;
+        }
+    }
+
+    /// Write raw bytes to the running pipeline's stdin
+    fn write_stdin(&mut self, data: &[u8]) {
+        let Some(stdin) = &mut self.stdin else {
+            return;
+        };
+
+        if let Err(e) = stdin.write_all(data).and_then(|_| stdin.flush()) {
+            self.stdin = None;
+            // A command that closed its stdin just doesn't get the input
+            if e.kind() != io::ErrorKind::BrokenPipe {
+                self.result.push_stderr(format!("Error: failed to send input: {}", e));
+            }
+        }
+    }
+}
@@ -1,27 +1,62 @@
 use anyhow::Result;
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers, MouseEvent, MouseEventKind},
+    event::{DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers, MouseEvent, MouseEventKind},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use ratatui::{
     backend::CrosstermBackend,
+    layout::Rect,
     Terminal,
 };
 use std::{
     io,
+    process::Command,
     time::{Duration, Instant},
 };
 
+mod alias;
+mod ansi;
+mod commands;
+mod config;
+mod direnv;
+mod events;
 mod executor;
+mod expand;
 mod history;
 mod input;
+mod jobs;
+mod output;
+mod parser;
+mod process;
+mod pty;
+mod scrollback;
+mod shell;
+mod sudo;
 mod ui;
+mod watch;
+mod watchdog;
 
+use alias::Aliases;
+use config::{Config, KeyBindings};
+use direnv::TrustStore;
+use events::{AppEvent, Events};
 use executor::Executor;
 use history::History;
 use input::InputState;
-use ui::UiState;
+use ui::{HoverPreview, UiState};
+
+/// Maximum number of glob matches collected for a hover preview
+const PREVIEW_MATCH_LIMIT: usize = 200;
+
+/// Maximum number of glob matches listed in a hover preview
+const PREVIEW_VISIBLE_MATCHES: usize = 10;
+
+/// How often the UI redraws while a command runs, to animate the spinner
+const RUNNING_TICK: Duration = Duration::from_millis(100);
+
+/// Lines the output pane scrolls for each step of the mouse wheel
+const WHEEL_SCROLL_LINES: isize = 3;
 
 /// Application state
 struct App {
@@ -33,6 +68,10 @@ struct App {
     history: History,
     /// Command executor
     executor: Executor,
+    /// Terminal input and command output, for the main loop to wait on
+    events: Events,
+    /// Configured key bindings
+    keybindings: KeyBindings,
     /// Whether the application should exit
     should_quit: bool,
 }
@@ -40,26 +79,55 @@ struct App {
 impl App {
     /// Create a new application
     fn new() -> Result<Self> {
-        // Load history
+        // Load configuration and history
+        let config = Config::load_default()?;
         let history = History::load_default()?;
 
+        let events = Events::new()?;
+        let mut executor = Executor::with_config(config.execution)
+            .with_aliases(Aliases {
+                aliases: config.aliases,
+                functions: config.functions,
+            })
+            .with_notifier(events.notifier())
+            .with_env_trust(TrustStore::load_default()?);
+
+        // Commands used before rank first when suggesting corrections
+        for idx in 0..history.len() {
+            if let Some(command) = history.get(idx) {
+                executor.remember_command(command);
+            }
+        }
+
         Ok(Self {
-            ui_state: UiState::default(),
+            ui_state: UiState {
+                error_color: config.colors.palette().error_output.0,
+                ..UiState::default()
+            },
             input_state: InputState::new(),
             history,
-            executor: Executor::new(),
+            executor,
+            events,
+            keybindings: config.keybindings,
             should_quit: false,
         })
     }
 
     /// Run the application
     fn run(&mut self, terminal: &mut Terminal<CrosstermBackend<io::Stdout>>) -> Result<()> {
-        let tick_rate = Duration::from_millis(100);
-        let mut last_tick = Instant::now();
-
         // Initialize the file list
         ui::update_file_list(&mut self.ui_state)?;
 
+        // Size command ptys to the output pane
+        self.sync_pty_size()?;
+
+        // Set up the session the way the user likes it
+        self.run_rc_file();
+
+        // Load the env files of the directory mouse_term starts in
+        self.executor.load_dir_env();
+        self.update_output();
+
         // Main event loop
         loop {
             // Draw the UI
@@ -70,37 +138,39 @@ impl App {
                 return Ok(());
             }
 
-            // Handle events
-            let timeout = tick_rate
-                .checked_sub(last_tick.elapsed())
-                .unwrap_or_else(|| Duration::from_secs(0));
-
-            if crossterm::event::poll(timeout)? {
-                self.handle_event(event::read()?)?;
+            // Sleep until there is input or output, or the screen needs a redraw
+            match self.events.next(self.tick()) {
+                Some(AppEvent::Input(event)) => self.handle_event(event)?,
+                Some(AppEvent::InputError(e)) => return Err(e.into()),
+                Some(AppEvent::Output) | None => {}
             }
 
             // Check for command output
             if self.executor.check_output() {
                 // Update the UI with new output
-                self.ui_state.output = self.executor.all_output();
-                self.ui_state.is_running = self.executor.is_running();
+                self.update_output();
 
-                // If the command was a cd, update the file list
-                if !self.ui_state.is_running && self.ui_state.output.iter().any(|line| line.starts_with("Changed directory to:")) {
-                    // Update the current directory
-                    self.ui_state.current_dir = std::env::current_dir()?;
+                // Input typed for a command that has exited is dropped
+                if !self.ui_state.is_running {
+                    self.ui_state.stdin_input.clear();
+                }
+            }
 
-                    // Update the file list
-                    ui::update_file_list(&mut self.ui_state)?;
+            // Ask again for a sudo password that was wrong or had expired
+            if let Some(prompt) = self.executor.take_sudo_prompt() {
+                self.ui_state.sudo_password_prompt = true;
+                self.ui_state.sudo_command = Some(prompt.command);
+                self.ui_state.sudo_wrong_password = prompt.wrong_password;
+            }
 
-                    // Set the needs_refresh flag to trigger a UI update
-                    self.ui_state.needs_refresh = true;
-                }
+            // Ask whether to trust an env file found on entering a directory
+            if let Some(file) = self.executor.take_env_trust_prompt() {
+                self.ui_state.env_trust_prompt = Some(file);
             }
 
-            // Check if it's time for a tick
-            if last_tick.elapsed() >= tick_rate {
-                last_tick = Instant::now();
+            // Hand the terminal to a full-screen program waiting to start
+            if let Some(cmd) = self.executor.take_fullscreen() {
+                self.run_fullscreen(terminal, cmd)?;
             }
 
             // Check if the UI needs to be refreshed
@@ -114,17 +184,205 @@ impl App {
         }
     }
 
+    /// Get how long the main loop may sleep before the screen needs a redraw
+    ///
+    /// A running command has a spinner to animate; otherwise only the clock
+    /// changes, at the start of each second. A watched command may be due
+    /// to run before then.
+    fn tick(&self) -> Duration {
+        let tick = if self.ui_state.is_running {
+            RUNNING_TICK
+        } else {
+            let millis = chrono::Local::now().timestamp_subsec_millis().min(999);
+            Duration::from_millis(u64::from(1000 - millis))
+        };
+        self.executor.watch_deadline()
+            .map_or(tick, |deadline| tick.min(deadline.saturating_duration_since(Instant::now())))
+    }
+
     /// Handle an event
     fn handle_event(&mut self, event: Event) -> Result<()> {
         match event {
-            Event::Key(key) => self.handle_key_event(key)?,
+            Event::Key(key) => {
+                self.handle_key_event(key)?;
+
+                // Typing may have changed the hovered token
+                self.update_hover_preview();
+            }
             Event::Mouse(mouse) => self.handle_mouse_event(mouse)?,
+            Event::Resize(_, _) => {
+                self.sync_pty_size()?;
+                self.load_output_window();
+            }
             _ => {}
         }
 
         Ok(())
     }
 
+    /// Run `~/.mouse_term/rc` if there is one, like a shell's rc file
+    ///
+    /// Its lines run through `source`, so errors are reported in the output
+    /// pane and the rest of the file still runs.
+    fn run_rc_file(&mut self) {
+        let Some(rc_path) = Config::rc_path().filter(|path| path.is_file()) else {
+            return;
+        };
+
+        self.executor.source_file(&rc_path);
+        self.update_output();
+    }
+
+    /// Show the output of the viewed job and the state of every job
+    fn update_output(&mut self) {
+        // Stay on the same lines while scrolled back, as new output arrives below
+        let len = self.executor.output_len();
+        if self.ui_state.output_scroll > 0 {
+            self.ui_state.output_scroll = len.checked_sub(self.ui_state.output_len)
+                .map_or(0, |added| self.ui_state.output_scroll + added);
+        }
+
+        self.ui_state.is_running = self.executor.is_running();
+        self.ui_state.jobs = self.executor.jobs();
+        self.ui_state.last_summary = self.executor.last_summary().map(str::to_string);
+        self.ui_state.dir_stack = self.executor.dir_stack().to_vec();
+        self.ui_state.suggestions = self.executor.suggestions().cloned();
+        self.ui_state.dir_env = self.executor.dir_env().map(|dir_env| {
+            let files: Vec<String> = dir_env.files.iter()
+                .filter_map(|file| file.file_name())
+                .map(|name| name.to_string_lossy().to_string())
+                .collect();
+            format!("{} · {} vars", files.join(" "), dir_env.names().count())
+        });
+        self.load_output_window();
+        self.sync_current_dir();
+    }
+
+    /// Fetch the output lines the output pane shows from the viewed job
+    ///
+    /// Only one screenful is copied, so even huge outputs, mostly kept on
+    /// disk, are cheap to show.
+    fn load_output_window(&mut self) {
+        let len = self.executor.output_len();
+        let rows = self.output_area().map_or(0, |area| usize::from(area.height.saturating_sub(2)));
+
+        // Scrolling stops at the first page
+        self.ui_state.output_scroll = self.ui_state.output_scroll.min(len.saturating_sub(rows));
+        let end = len - self.ui_state.output_scroll;
+        let start = end.saturating_sub(rows);
+
+        self.ui_state.output = self.executor.output_lines(start..end);
+        self.ui_state.output_changed = self.executor.watch_changes(start, &self.ui_state.output);
+        self.ui_state.output_start = start;
+        self.ui_state.output_len = len;
+    }
+
+    /// Scroll the output pane back by some lines, or forward for negative `lines`
+    fn scroll_output(&mut self, lines: isize) {
+        self.ui_state.output_scroll = self.ui_state.output_scroll.saturating_add_signed(lines);
+        self.load_output_window();
+    }
+
+    /// Get where the output pane is on screen
+    fn output_area(&self) -> Result<Rect> {
+        let size = crossterm::terminal::size()?;
+        let term_rect = Rect::new(0, 0, size.0, size.1);
+
+        let (main_area, _, _, _) = ui::calculate_layout(term_rect, self.ui_state.show_history);
+        let (output_area, _, _, _) = ui::split_main_area(
+            main_area,
+            !self.ui_state.jobs.is_empty(),
+            !self.ui_state.dir_stack.is_empty(),
+        );
+        Ok(output_area)
+    }
+
+    /// Follow the working directory when cd, pushd or popd changed it
+    fn sync_current_dir(&mut self) {
+        let Ok(current_dir) = std::env::current_dir() else {
+            return;
+        };
+        if current_dir == self.ui_state.current_dir {
+            return;
+        }
+
+        self.ui_state.current_dir = current_dir;
+        self.ui_state.hover_file = None;
+        if ui::update_file_list(&mut self.ui_state).is_err() {
+            self.ui_state.files.clear();
+        }
+        self.ui_state.needs_refresh = true;
+    }
+
+    /// Refresh the preview popup for the hovered token
+    fn update_hover_preview(&mut self) {
+        let token = self.ui_state.hover_token
+            .and_then(|idx| self.input_state.tokens.get(idx).map(|token| (idx, token)));
+
+        let Some((idx, token)) = token else {
+            self.ui_state.hover_preview = None;
+            return;
+        };
+
+        // Globbing a large tree is slow, so only rebuild when the token changes
+        if let Some(preview) = &self.ui_state.hover_preview
+            && preview.token == idx
+            && preview.text == token.text
+            && expand::is_glob_pattern(&token.text)
+        {
+            return;
+        }
+
+        self.ui_state.hover_preview = if expand::is_glob_pattern(&token.text) {
+            let matches = self.executor.preview_expansion(&token.text, PREVIEW_MATCH_LIMIT);
+            let count = if matches.len() >= PREVIEW_MATCH_LIMIT {
+                format!("{}+", PREVIEW_MATCH_LIMIT)
+            } else {
+                matches.len().to_string()
+            };
+
+            let mut lines: Vec<String> = matches.iter().take(PREVIEW_VISIBLE_MATCHES).cloned().collect();
+            if matches.len() > PREVIEW_VISIBLE_MATCHES {
+                lines.push(format!("… and {} more", matches.len() - PREVIEW_VISIBLE_MATCHES));
+            }
+
+            // A pattern that matches nothing is passed to the program as is
+            let title = if matches.len() == 1 && matches[0] == token.text {
+                lines = vec![String::from("No matches, passed literally")];
+                format!(" {} ", token.text)
+            } else {
+                format!(" {} → {} ", token.text, count)
+            };
+
+            Some(HoverPreview {
+                token: idx,
+                text: token.text.clone(),
+                title,
+                lines,
+            })
+        } else if let Some((kind, expansion)) = self.executor.aliases().describe(&self.input_state.tokens, idx) {
+            // Show what an alias or function runs, with the arguments filled in
+            Some(HoverPreview {
+                token: idx,
+                text: token.text.clone(),
+                title: format!(" {} → {} ", token.text, kind),
+                lines: vec![expansion],
+            })
+        } else {
+            None
+        };
+    }
+
+    /// Keep the pty size used for commands in line with the output pane
+    fn sync_pty_size(&mut self) -> Result<()> {
+        let output_area = self.output_area()?;
+
+        // Leave room for the pane border
+        self.executor.resize(output_area.height.saturating_sub(2), output_area.width.saturating_sub(2));
+
+        Ok(())
+    }
+
     /// Handle a key event
     fn handle_key_event(&mut self, key: KeyEvent) -> Result<()> {
         // Check if we're waiting for a sudo password
@@ -135,6 +393,7 @@ impl App {
                     self.ui_state.sudo_password_prompt = false;
                     self.ui_state.sudo_password.clear();
                     self.ui_state.sudo_command = None;
+                    self.ui_state.sudo_wrong_password = false;
 
                     // Set the needs_refresh flag to trigger a UI update
                     self.ui_state.needs_refresh = true;
@@ -142,79 +401,14 @@ impl App {
                 KeyCode::Enter => {
                     // Submit the password
                     if let Some(cmd) = self.ui_state.sudo_command.take() {
-                        // Execute the command with the password
-                        let password = self.ui_state.sudo_password.clone();
-                        self.ui_state.sudo_password.clear();
+                        // Execute the command with the password, which is
+                        // wiped once sudo has it
+                        let password = std::mem::take(&mut self.ui_state.sudo_password);
                         self.ui_state.sudo_password_prompt = false;
+                        self.ui_state.sudo_wrong_password = false;
 
-                        // Check if this is a nano command
-                        if cmd.starts_with("sudo nano ") {
-                            // Extract the filename
-                            let parts: Vec<&str> = cmd.split_whitespace().collect();
-                            if parts.len() >= 3 {
-                                let filename = parts[2];
-                                let file_path = if filename.starts_with('/') {
-                                    std::path::PathBuf::from(filename)
-                                } else {
-                                    self.ui_state.current_dir.join(filename)
-                                };
-
-                                // Clear previous output
-                                self.ui_state.output.clear();
-
-                                // Add file name as header
-                                self.ui_state.output.push(format!("File: {}", file_path.display()));
-                                self.ui_state.output.push(String::from("-----------------------------------"));
-
-                                // Try to read the file content with sudo
-                                let output = std::process::Command::new("sudo")
-                                    .arg("-S")
-                                    .arg("cat")
-                                    .arg(&file_path)
-                                    .stdin(std::process::Stdio::piped())
-                                    .stdout(std::process::Stdio::piped())
-                                    .stderr(std::process::Stdio::piped())
-                                    .spawn()
-                                    .and_then(|mut child| {
-                                        if let Some(mut stdin) = child.stdin.take() {
-                                            use std::io::Write;
-                                            stdin.write_all(format!("{}\n", password).as_bytes())?;
-                                        }
-                                        child.wait_with_output()
-                                    });
-
-                                match output {
-                                    Ok(output) => {
-                                        if output.status.success() {
-                                            // Convert output to string and split by lines
-                                            if let Ok(content) = String::from_utf8(output.stdout) {
-                                                for line in content.lines() {
-                                                    self.ui_state.output.push(line.to_string());
-                                                }
-                                            } else {
-                                                self.ui_state.output.push(String::from("Error: File contains non-UTF8 characters"));
-                                            }
-                                        } else {
-                                            // Command failed
-                                            if let Ok(err) = String::from_utf8(output.stderr) {
-                                                self.ui_state.output.push(format!("Error: {}", err));
-                                            } else {
-                                                self.ui_state.output.push(String::from("Error: Failed to read file with sudo"));
-                                            }
-                                        }
-                                    },
-                                    Err(e) => {
-                                        self.ui_state.output.push(format!("Error executing sudo: {}", e));
-                                    }
-                                }
-                            } else {
-                                self.ui_state.output.push(String::from("Error: Invalid nano command format"));
-                            }
-                        } else {
-                            // Execute other sudo commands normally
-                            self.executor.execute_sudo(&cmd, &password)?;
-                            self.ui_state.is_running = true;
-                        }
+                        self.executor.execute_sudo(&cmd, Some(password))?;
+                        self.update_output();
 
                         // Set the needs_refresh flag to trigger a UI update
                         self.ui_state.needs_refresh = true;
@@ -238,6 +432,43 @@ impl App {
             return Ok(());
         }
 
+        // Check if we're asking whether to trust an env file
+        if let Some(file) = self.ui_state.env_trust_prompt.take() {
+            match key.code {
+                KeyCode::Char('y' | 'Y') => self.executor.trust_env_file(&file, true),
+                KeyCode::Char('n' | 'N') => self.executor.trust_env_file(&file, false),
+                // Any other key leaves the file to be asked about next time
+                _ => {}
+            }
+            self.update_output();
+            return Ok(());
+        }
+
+        // Check if we're asking whether to quit
+        if self.ui_state.quit_confirm {
+            self.ui_state.quit_confirm = false;
+            if matches!(key.code, KeyCode::Char('y' | 'Y')) || self.keybindings.quit.matches(&key) {
+                self.executor.terminate();
+                self.should_quit = true;
+            }
+            return Ok(());
+        }
+
+        // Quit, asking first if that would kill a running command or job
+        if self.keybindings.quit.matches(&key) {
+            if self.executor.has_jobs() {
+                self.ui_state.quit_confirm = true;
+            } else {
+                self.should_quit = true;
+            }
+            return Ok(());
+        }
+
+        // While a command runs, typing goes to its stdin
+        if self.executor.is_running() && self.handle_stdin_key(key) {
+            return Ok(());
+        }
+
         // Check if we're editing a token
         if let Some(idx) = self.ui_state.editing_token {
             match key.code {
@@ -273,8 +504,25 @@ impl App {
         // Global key handlers
         match key.code {
             KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                // Ctrl+C: Exit the application
-                self.should_quit = true;
+                // Ctrl+C: Stop watching and interrupt the running command, or
+                // discard the input line
+                let stopped = self.executor.stop_watch();
+                if self.executor.interrupt().is_some() || stopped {
+                    self.update_output();
+                } else {
+                    self.input_state.clear();
+                }
+            }
+            KeyCode::Char('\\' | '4') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                // Ctrl+\: Escalate to a signal the command cannot ignore as easily
+                self.executor.escalate();
+                self.update_output();
+            }
+            KeyCode::Char('z') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                // Ctrl+Z: Stop the running command and keep it as a job
+                self.executor.suspend();
+                self.ui_state.stdin_input.clear();
+                self.update_output();
             }
             KeyCode::Char('l') if key.modifiers.contains(KeyModifiers::CONTROL) => {
                 // Ctrl+L: Clear the screen
@@ -283,10 +531,12 @@ impl App {
             KeyCode::F(2) => {
                 // F2: Toggle history sidebar
                 self.ui_state.show_history = !self.ui_state.show_history;
+                self.sync_pty_size()?;
             }
             KeyCode::Char('h') if key.modifiers.contains(KeyModifiers::CONTROL) => {
                 // Ctrl+H: Alternative way to toggle history sidebar
                 self.ui_state.show_history = !self.ui_state.show_history;
+                self.sync_pty_size()?;
             }
             KeyCode::Enter => {
                 // Enter: Execute the command
@@ -295,21 +545,39 @@ impl App {
                     // Add to history
                     self.history.add(command.clone());
 
-                    // Check if this is a sudo command
-                    if command.trim().starts_with("sudo ") {
-                        // Prompt for password
-                        self.ui_state.sudo_password_prompt = true;
-                        self.ui_state.sudo_command = Some(command.clone());
+                    // Check if this is a sudo command, asking for the password
+                    // unless sudo still has it
+                    if self.executor.is_sudo_command(&command) {
+                        if self.executor.sudo_credentials_cached() {
+                            self.ui_state.output_scroll = 0;
+                            self.executor.execute_sudo(&command, None)?;
+                            self.update_output();
+                        } else {
+                            // Prompt for password
+                            self.ui_state.sudo_password_prompt = true;
+                            self.ui_state.sudo_command = Some(command.clone());
+                        }
                     } else {
-                        // Execute the command
+                        // Execute the command, showing its output from the end
+                        self.ui_state.output_scroll = 0;
                         self.executor.execute(&command)?;
-                        self.ui_state.is_running = true;
+                        self.update_output();
                     }
 
                     // Clear the input
                     self.input_state.clear();
                 }
             }
+            KeyCode::PageUp => {
+                // PageUp: Scroll the output back a page
+                let page = self.output_area()?.height.saturating_sub(2);
+                self.scroll_output(page as isize);
+            }
+            KeyCode::PageDown => {
+                // PageDown: Scroll the output forward a page
+                let page = self.output_area()?.height.saturating_sub(2);
+                self.scroll_output(-(page as isize));
+            }
             KeyCode::Up => {
                 // Up: Navigate history backward
                 if let Some(prev_cmd) = self.history.previous() {
@@ -343,6 +611,63 @@ impl App {
         Ok(())
     }
 
+    /// Suspend the UI and give the terminal to a full-screen program until it exits
+    fn run_fullscreen(&mut self, terminal: &mut Terminal<CrosstermBackend<io::Stdout>>, mut cmd: Command) -> Result<()> {
+        // Stop reading input so every key goes to the program, then leave the
+        // alternate screen and raw mode so it sees a normal terminal
+        self.events.pause();
+        disable_raw_mode()?;
+        execute!(terminal.backend_mut(), LeaveAlternateScreen, DisableMouseCapture)?;
+        terminal.show_cursor()?;
+
+        let status = cmd.spawn().and_then(|mut child| process::wait_foreground(&mut child));
+
+        // Bring the UI back and redraw it from scratch
+        enable_raw_mode()?;
+        execute!(terminal.backend_mut(), EnterAlternateScreen, EnableMouseCapture)?;
+        terminal.clear()?;
+        self.events.resume();
+
+        // Report the exit code and carry on with the rest of the command list
+        self.executor.finish_fullscreen(status);
+        self.update_output();
+
+        // The program may have created, renamed or deleted files
+        ui::update_file_list(&mut self.ui_state)?;
+        self.ui_state.needs_refresh = true;
+
+        Ok(())
+    }
+
+    /// Handle a key event meant for the running command's stdin
+    ///
+    /// Returns false for keys that should still reach the global handlers.
+    fn handle_stdin_key(&mut self, key: KeyEvent) -> bool {
+        match key.code {
+            KeyCode::Char('d') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                // Ctrl+D: End of input
+                let pending = std::mem::take(&mut self.ui_state.stdin_input);
+                self.executor.send_eof(&pending);
+            }
+            KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.ui_state.stdin_input.push(c);
+            }
+            KeyCode::Backspace => {
+                self.ui_state.stdin_input.pop();
+            }
+            KeyCode::Enter => {
+                // Send the line to the command
+                let line = std::mem::take(&mut self.ui_state.stdin_input);
+                self.executor.send_line(&line);
+            }
+            _ => return false,
+        }
+
+        // Show echoed input right away
+        self.update_output();
+        true
+    }
+
     /// Handle a mouse event
     fn handle_mouse_event(&mut self, mouse: MouseEvent) -> Result<()> {
         match mouse.kind {
@@ -354,25 +679,14 @@ impl App {
                 // Calculate layout using the same function as rendering
                 let (main_area, _, input_area, history_area) = ui::calculate_layout(term_rect, self.ui_state.show_history);
 
-                // Calculate output and file list areas
-                let (_output_area, file_list_area) = if main_area.height >= 6 { // Minimum height for both sections
-                    let output_height = std::cmp::max(3, (main_area.height as f32 * 0.6) as u16);
-                    let file_list_height = std::cmp::max(3, main_area.height.saturating_sub(output_height));
-
-                    let chunks = ratatui::layout::Layout::default()
-                        .direction(ratatui::layout::Direction::Vertical)
-                        .constraints([
-                            ratatui::layout::Constraint::Min(output_height),
-                            ratatui::layout::Constraint::Min(file_list_height),
-                        ])
-                        .split(main_area);
-
-                    (Some(chunks[0]), Some(chunks[1]))
-                } else {
-                    (Some(main_area), None)
-                };
+                // Calculate output, file list, jobs and directory stack areas
+                let (output_area, file_list_area, jobs_area, dirs_area) = ui::split_main_area(
+                    main_area,
+                    !self.ui_state.jobs.is_empty(),
+                    !self.ui_state.dir_stack.is_empty(),
+                );
 
-                if mouse.row >= input_area.y && mouse.row < input_area.y + input_area.height {
+                if mouse.row >= input_area.y && mouse.row < input_area.y + input_area.height && !self.executor.is_running() {
                     // Click in the input area
                     if let Some(token_idx) = ui::get_token_at_position(
                         &self.input_state,
@@ -383,6 +697,39 @@ impl App {
                         self.input_state.start_editing(token_idx)?;
                         self.ui_state.editing_token = Some(token_idx);
                     }
+                } else if let Some(idx) = ui::get_suggestion_at_position(&self.ui_state, mouse.column, mouse.row, output_area) {
+                    // Click on a correction - put the corrected command in the input line to check and run
+                    if let Some(command) = self.ui_state.suggestions.as_ref().and_then(|suggestions| suggestions.corrected(idx))
+                        && !self.executor.is_running()
+                    {
+                        self.input_state.set_input(command)?;
+                    }
+                } else if let Some(dirs_area) = dirs_area
+                    && mouse.row == dirs_area.y
+                {
+                    // Click in the directory stack - rotate that directory to the top
+                    if let Some(idx) = ui::get_dir_at_position(&self.ui_state, mouse.column, dirs_area)
+                        && idx > 0
+                        && !self.executor.is_running()
+                    {
+                        let pushd_command = format!("pushd +{}", idx);
+                        self.history.add(pushd_command.clone());
+                        self.executor.execute(&pushd_command)?;
+                        self.update_output();
+                        self.input_state.clear();
+                    }
+                } else if let Some(jobs_area) = jobs_area
+                    && mouse.column >= jobs_area.x
+                    && mouse.row >= jobs_area.y
+                    && mouse.row < jobs_area.y + jobs_area.height
+                {
+                    // Click in the jobs panel - show that job's output, or the
+                    // foreground output again when it is already shown
+                    if let Some(number) = ui::get_job_at_position(&self.ui_state, mouse.row, jobs_area) {
+                        self.executor.select_job(number);
+                        self.update_output();
+                        self.ui_state.needs_refresh = true;
+                    }
                 } else if let Some(file_area) = file_list_area {
                     if mouse.row >= file_area.y && mouse.row < file_area.y + file_area.height {
                         // Click in the file list area
@@ -402,76 +749,51 @@ impl App {
                             let file = &self.ui_state.files[file_idx];
 
                             if file.is_dir {
-                                // Click on a directory - cd into it
-                                let cd_command = format!("cd {}", file.name);
+                                // Click on a directory - cd into it, which also updates the file list
+                                let cd_command = format!("cd {}", input::quote_literal(&file.name));
                                 self.history.add(cd_command.clone());
                                 self.executor.execute(&cd_command)?;
-                                self.ui_state.is_running = true;
+                                self.update_output();
                                 self.input_state.clear();
 
-                                // Update the current directory directly
-                                let new_dir = self.ui_state.current_dir.join(&file.name);
-                                if new_dir.exists() && new_dir.is_dir() {
-                                    self.ui_state.current_dir = new_dir;
-                                    // Update the file list
-                                    ui::update_file_list(&mut self.ui_state)?;
-                                }
-
                                 // Set the needs_refresh flag to trigger a UI update
                                 self.ui_state.needs_refresh = true;
                             } else {
-                                // Click on a file - read and display its content
-                                let file_path = self.ui_state.current_dir.join(&file.name);
-
-                                // Clear previous output
-                                self.ui_state.output.clear();
-
-                                // Add file name as header
-                                self.ui_state.output.push(format!("File: {}", file_path.display()));
-                                self.ui_state.output.push(String::from("-----------------------------------"));
-
-                                // Try to read the file content
-                                match std::fs::read_to_string(&file_path) {
-                                    Ok(content) => {
-                                        // Split content by lines and add to output
-                                        for line in content.lines() {
-                                            self.ui_state.output.push(line.to_string());
-                                        }
-                                    },
-                                    Err(e) => {
-                                        // Handle error (e.g., permission denied)
-                                        self.ui_state.output.push(format!("Error reading file: {}", e));
-
-                                        // If permission denied, suggest using sudo
-                                        if e.kind() == std::io::ErrorKind::PermissionDenied {
-                                            self.ui_state.output.push(String::from(""));
-                                            self.ui_state.output.push(String::from("This file requires elevated permissions to read."));
-                                            self.ui_state.output.push(String::from("Try using the terminal command: sudo cat <filename>"));
-                                        }
-                                    }
-                                }
-
-                                // Add to history
-                                let view_command = format!("view {}", file.name);
-                                self.history.add(view_command);
+                                // Click on a file - open it in the editor
+                                let edit_command = self.executor.edit_command(&file.name);
+                                self.history.add(edit_command.clone());
+                                self.executor.execute(&edit_command)?;
+                                self.update_output();
+                                self.input_state.clear();
 
                                 // Set the needs_refresh flag to trigger a UI update
                                 self.ui_state.needs_refresh = true;
                             }
                         }
                     }
-                } else if let Some(history_area) = history_area {
-                    if mouse.row >= history_area.y && mouse.row < history_area.y + history_area.height {
-                        // Click in the history sidebar
-                        let history_idx = (mouse.row - history_area.y) as usize;
-                        if history_idx < self.history.len() {
-                            if let Some(cmd) = self.history.get(history_idx) {
-                                self.input_state.set_input(cmd.clone())?;
-                            }
-                        }
+                } else if let Some(history_area) = history_area
+                    && mouse.row >= history_area.y
+                    && mouse.row < history_area.y + history_area.height
+                {
+                    // Click in the history sidebar
+                    let history_idx = (mouse.row - history_area.y) as usize;
+                    if history_idx < self.history.len()
+                        && let Some(cmd) = self.history.get(history_idx)
+                    {
+                        self.input_state.set_input(cmd.clone())?;
                     }
                 }
             }
+            MouseEventKind::ScrollUp | MouseEventKind::ScrollDown => {
+                // Wheel over the output pane: scroll the output
+                let area = self.output_area()?;
+                let over_output = mouse.column >= area.x && mouse.column < area.x + area.width
+                    && mouse.row >= area.y && mouse.row < area.y + area.height;
+                if over_output {
+                    let lines = if mouse.kind == MouseEventKind::ScrollUp { WHEEL_SCROLL_LINES } else { -WHEEL_SCROLL_LINES };
+                    self.scroll_output(lines);
+                }
+            }
             MouseEventKind::Moved => {
                 // Get the terminal size
                 let size = crossterm::terminal::size()?;
@@ -481,22 +803,11 @@ impl App {
                 let (main_area, _, input_area, _) = ui::calculate_layout(term_rect, self.ui_state.show_history);
 
                 // Calculate output and file list areas
-                let (_, file_list_area) = if main_area.height >= 6 { // Minimum height for both sections
-                    let output_height = std::cmp::max(3, (main_area.height as f32 * 0.6) as u16);
-                    let file_list_height = std::cmp::max(3, main_area.height.saturating_sub(output_height));
-
-                    let chunks = ratatui::layout::Layout::default()
-                        .direction(ratatui::layout::Direction::Vertical)
-                        .constraints([
-                            ratatui::layout::Constraint::Min(output_height),
-                            ratatui::layout::Constraint::Min(file_list_height),
-                        ])
-                        .split(main_area);
-
-                    (Some(chunks[0]), Some(chunks[1]))
-                } else {
-                    (Some(main_area), None)
-                };
+                let (_, file_list_area, _, _) = ui::split_main_area(
+                    main_area,
+                    !self.ui_state.jobs.is_empty(),
+                    !self.ui_state.dir_stack.is_empty(),
+                );
 
                 if mouse.row >= input_area.y && mouse.row < input_area.y + input_area.height {
                     // Mouse over the input area
@@ -506,8 +817,12 @@ impl App {
                         input_area,
                     );
                     self.ui_state.hover_file = None;
+                    self.update_hover_preview();
                 } else if let Some(file_area) = file_list_area {
-                    if mouse.row >= file_area.y && mouse.row < file_area.y + file_area.height {
+                    if mouse.row >= file_area.y
+                        && mouse.row < file_area.y + file_area.height
+                        && mouse.column < file_area.x + file_area.width
+                    {
                         // Mouse over the file list area
                         let effective_file_area = if self.ui_state.show_history {
                             // Adjust width if history sidebar is shown
@@ -527,9 +842,11 @@ impl App {
                         self.ui_state.hover_token = None;
                         self.ui_state.hover_file = None;
                     }
+                    self.update_hover_preview();
                 } else {
                     self.ui_state.hover_token = None;
                     self.ui_state.hover_file = None;
+                    self.update_hover_preview();
                 }
             }
             _ => {}
@@ -540,6 +857,11 @@ impl App {
 }
 
 fn main() -> Result<()> {
+    // sudo runs mouse_term again to fetch the password typed into the prompt
+    if let Some(socket) = std::env::var_os(sudo::ASKPASS_SOCKET_VAR) {
+        std::process::exit(sudo::run_askpass(socket.as_ref()));
+    }
+
     // Setup terminal
     enable_raw_mode()?;
     let mut stdout = io::stdout();
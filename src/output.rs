@@ -0,0 +1,284 @@
+/// Longest escape sequence kept before it is given up on as garbage
+const MAX_SEQUENCE_LEN: usize = 256;
+
+/// Most characters a line holds before it is ended as if a newline came,
+/// so output without newlines, such as minified JSON, can't grow one line
+/// without bound
+const MAX_LINE_WIDTH: usize = 16 * 1024;
+
+/// Turns raw command output into lines the way a terminal would show them
+///
+/// Bytes are decoded lossily, so invalid UTF-8 never loses a line. `\r`
+/// moves back to the start of the line and `\x08` back one character, so
+/// whatever is written next overwrites what was there, which is how progress
+/// bars redraw in place. `ESC [ K` erases to the end of the line. Color and
+/// attribute sequences (`ESC [ ... m`) are kept in the text; other escape
+/// sequences are dropped. Lines longer than [`MAX_LINE_WIDTH`] characters
+/// are split.
+#[derive(Debug, Default)]
+pub struct LineBuffer {
+    /// Text of the line being written, with color sequences in front of the
+    /// characters they apply to
+    text: String,
+    /// Where each character of the line starts in `text`, including the
+    /// color sequences written just before it
+    columns: Vec<usize>,
+    /// Position the next character is written to
+    cursor: usize,
+    /// Color sequences waiting for the character they apply to
+    styles: String,
+    /// An escape sequence that has not ended yet
+    sequence: Option<String>,
+    /// Bytes of a character split across reads
+    undecoded: Vec<u8>,
+    /// Whether the unfinished line changed since it was last taken
+    changed: bool,
+    /// Whether the unfinished line has been taken, and so is on screen
+    shown: bool,
+}
+
+impl LineBuffer {
+    /// Process a chunk of output, returning the lines it completed
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        let text = self.decode(bytes);
+        let mut lines = Vec::new();
+
+        for c in text.chars() {
+            if let Some(mut sequence) = self.sequence.take() {
+                sequence.push(c);
+                if Self::is_complete(&sequence) {
+                    self.apply_sequence(&sequence);
+                } else if sequence.len() < MAX_SEQUENCE_LEN {
+                    self.sequence = Some(sequence);
+                }
+                continue;
+            }
+
+            match c {
+                '\n' => lines.push(self.take_line()),
+                '\r' => self.cursor = 0,
+                '\x08' => self.cursor = self.cursor.saturating_sub(1),
+                '\x1b' => self.sequence = Some(c.to_string()),
+                // Other control characters, such as the bell, have no text
+                c if c.is_control() && c != '\t' => {}
+                c => {
+                    if self.cursor >= MAX_LINE_WIDTH {
+                        lines.push(self.take_line());
+                    }
+                    self.write(c);
+                }
+            }
+        }
+
+        lines
+    }
+
+    /// Get the unfinished line if it changed since the last call
+    pub fn take_partial(&mut self) -> Option<String> {
+        if !self.changed || (self.columns.is_empty() && !self.shown) {
+            return None;
+        }
+
+        self.changed = false;
+        self.shown = true;
+        Some(self.line())
+    }
+
+    /// Finish the output, returning the last line if it had no newline
+    pub fn finish(&mut self) -> Option<String> {
+        if !self.undecoded.is_empty() {
+            let rest = std::mem::take(&mut self.undecoded);
+            for c in String::from_utf8_lossy(&rest).chars() {
+                self.write(c);
+            }
+        }
+
+        if self.columns.is_empty() && !self.shown {
+            return None;
+        }
+        Some(self.take_line())
+    }
+
+    /// Decode as much of the input as possible, keeping a split character for later
+    fn decode(&mut self, bytes: &[u8]) -> String {
+        self.undecoded.extend_from_slice(bytes);
+
+        let mut text = String::new();
+        let mut rest = self.undecoded.as_slice();
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(valid) => {
+                    text.push_str(valid);
+                    rest = &[];
+                    break;
+                }
+                Err(e) => {
+                    let (valid, invalid) = rest.split_at(e.valid_up_to());
+                    text.push_str(&String::from_utf8_lossy(valid));
+                    match e.error_len() {
+                        Some(len) => {
+                            text.push(char::REPLACEMENT_CHARACTER);
+                            rest = &invalid[len..];
+                        }
+                        // The character continues in the next read
+                        None => {
+                            rest = invalid;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        self.undecoded = rest.to_vec();
+        text
+    }
+
+    /// Check whether an escape sequence has ended
+    fn is_complete(sequence: &str) -> bool {
+        let mut chars = sequence.chars().skip(1);
+        match chars.next() {
+            None => false,
+            // CSI sequences end with a character from `@` to `~`
+            Some('[') => chars.last().is_some_and(|c| ('@'..='~').contains(&c)),
+            // OSC sequences, such as window titles, end with BEL or ESC \
+            Some(']') => sequence.ends_with('\x07') || sequence.ends_with("\x1b\\"),
+            Some(_) => true,
+        }
+    }
+
+    /// Act on a complete escape sequence
+    fn apply_sequence(&mut self, sequence: &str) {
+        let Some(csi) = sequence.strip_prefix("\x1b[") else {
+            return;
+        };
+
+        match csi.split_at(csi.len() - 1) {
+            (_, "m") => self.styles.push_str(sequence),
+            // Erase to the end, to the start, or the whole line
+            ("" | "0", "K") => {
+                if let Some(&start) = self.columns.get(self.cursor) {
+                    self.text.truncate(start);
+                    self.columns.truncate(self.cursor);
+                }
+            }
+            ("1", "K") => {
+                let end = self.cursor.min(self.columns.len());
+                let rest = self.columns.get(end).copied().unwrap_or(self.text.len());
+                self.text.replace_range(..rest, &" ".repeat(end));
+                for (column, start) in self.columns.iter_mut().enumerate() {
+                    *start = if column < end { column } else { *start + end - rest };
+                }
+            }
+            ("2", "K") => {
+                self.text.clear();
+                self.columns.clear();
+            }
+            _ => return,
+        }
+        self.changed = true;
+    }
+
+    /// Write a character at the cursor, overwriting what is there
+    fn write(&mut self, c: char) {
+        // The cursor may have been moved past the end of the line
+        while self.columns.len() < self.cursor {
+            self.columns.push(self.text.len());
+            self.text.push(' ');
+        }
+
+        self.styles.push(c);
+        if let Some(&start) = self.columns.get(self.cursor) {
+            let end = self.columns.get(self.cursor + 1).copied().unwrap_or(self.text.len());
+            self.text.replace_range(start..end, &self.styles);
+
+            // Later characters move when the new one is a different length
+            if self.styles.len() != end - start {
+                for later in &mut self.columns[self.cursor + 1..] {
+                    *later = *later + self.styles.len() - (end - start);
+                }
+            }
+        } else {
+            self.columns.push(self.text.len());
+            self.text.push_str(&self.styles);
+        }
+        self.styles.clear();
+
+        self.cursor += 1;
+        self.changed = true;
+    }
+
+    /// Get the text of the unfinished line
+    fn line(&self) -> String {
+        format!("{}{}", self.text, self.styles)
+    }
+
+    /// Complete the current line and start a new one
+    fn take_line(&mut self) -> String {
+        let line = self.line();
+        self.text.clear();
+        self.columns.clear();
+        self.styles.clear();
+        self.cursor = 0;
+        self.changed = false;
+        self.shown = false;
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_buffer_progress() {
+        let mut buffer = LineBuffer::default();
+
+        assert!(buffer.push(b"10%").is_empty());
+        assert_eq!(buffer.take_partial(), Some("10%".to_string()));
+        assert_eq!(buffer.take_partial(), None);
+        assert!(buffer.push(b"\r55%\r").is_empty());
+        assert_eq!(buffer.take_partial(), Some("55%".to_string()));
+        assert_eq!(buffer.push(b"100%\r\n"), ["100%"]);
+        assert_eq!(buffer.push(b"abc\x08\x08X\n"), ["aXc"]);
+        assert_eq!(buffer.push(b"long line\r\x1b[Kshort\n"), ["short"]);
+        assert_eq!(buffer.push(b"\x1b[31mred\x1b[0m\x1b]0;title\x07\n"), ["\x1b[31mred\x1b[0m"]);
+        assert_eq!(buffer.finish(), None);
+    }
+
+    #[test]
+    fn test_line_buffer_decoding() {
+        let mut buffer = LineBuffer::default();
+
+        // A character split across reads is put back together
+        assert!(buffer.push(&[b'h', 0xc3]).is_empty());
+        assert_eq!(buffer.push(&[0xa9, b'\n']), ["hé"]);
+        assert_eq!(buffer.push(b"bad \xff byte\n"), ["bad \u{fffd} byte"]);
+        assert!(buffer.push(b"prompt> ").is_empty());
+        assert_eq!(buffer.finish(), Some("prompt> ".to_string()));
+    }
+
+    #[test]
+    fn test_line_buffer_overwrite() {
+        let mut buffer = LineBuffer::default();
+
+        // Overwritten characters can have a different length or a color
+        assert_eq!(buffer.push("ébc\rx\n".as_bytes()), ["xbc"]);
+        assert_eq!(buffer.push(b"abc\r\x1b[1mX\x1b[0m\n"), ["\x1b[1mXbc\x1b[0m"]);
+        assert_eq!(buffer.push("aébcd\r\x1b[31mé\x1b[0mb\n".as_bytes()), ["\x1b[31mé\x1b[0mbbcd"]);
+        assert_eq!(buffer.push(b"abcdef\x08\x08\x1b[1K\n"), ["    ef"]);
+        assert_eq!(buffer.push(b"abcdef\x08\x08\x1b[2Kx\n"), ["    x"]);
+    }
+
+    #[test]
+    fn test_line_buffer_long_line() {
+        let mut buffer = LineBuffer::default();
+
+        // A line without a newline is split instead of growing forever
+        let lines = buffer.push(&[b'x'; MAX_LINE_WIDTH * 2 + 10]);
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|line| line.len() == MAX_LINE_WIDTH));
+        assert_eq!(buffer.take_partial(), Some("x".repeat(10)));
+        assert_eq!(buffer.finish(), Some("x".repeat(10)));
+    }
+}
@@ -0,0 +1,401 @@
+use std::fmt;
+use thiserror::Error;
+
+use crate::input::{self, InputError, TokenKind};
+
+/// Errors that can occur while parsing a command line
+#[derive(Error, Debug, PartialEq)]
+pub enum ParseError {
+    #[error("Empty command")]
+    EmptyCommand,
+
+    #[error("Syntax error near `{0}`")]
+    UnexpectedOperator(String),
+
+    #[error("Missing file name after `{0}`")]
+    MissingRedirectTarget(String),
+
+    #[error(transparent)]
+    Input(#[from] InputError),
+}
+
+/// Where a redirection sends or takes its data
+#[derive(Debug, Clone, PartialEq)]
+pub enum Redirect {
+    /// `< file`: read stdin from a file
+    Input(String),
+    /// `> file` or `>> file`: write stdout to a file
+    Output { path: String, append: bool },
+    /// `2> file` or `2>> file`: write stderr to a file
+    Error { path: String, append: bool },
+    /// `2>&1`: send stderr wherever stdout currently goes
+    ErrorToOutput,
+    /// `&> file` or `&>> file`: write both stdout and stderr to a file
+    Both { path: String, append: bool },
+}
+
+impl Redirect {
+    /// Apply `f` to the file name of the redirection, if it has one
+    pub fn map_path(&self, f: impl FnOnce(&str) -> String) -> Self {
+        match self {
+            Redirect::Input(path) => Redirect::Input(f(path)),
+            Redirect::Output { path, append } => Redirect::Output { path: f(path), append: *append },
+            Redirect::Error { path, append } => Redirect::Error { path: f(path), append: *append },
+            Redirect::ErrorToOutput => Redirect::ErrorToOutput,
+            Redirect::Both { path, append } => Redirect::Both { path: f(path), append: *append },
+        }
+    }
+}
+
+/// A single program invocation with its redirections
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SimpleCommand {
+    /// The program followed by its arguments
+    pub words: Vec<String>,
+    /// Redirections in the order they appeared
+    pub redirects: Vec<Redirect>,
+}
+
+/// Commands connected with `|`, each feeding its stdout to the next
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Pipeline {
+    /// The stages of the pipeline
+    pub commands: Vec<SimpleCommand>,
+}
+
+/// How a pipeline in a command list depends on the one before it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Connector {
+    /// Always run (first pipeline, or after `;`)
+    Always,
+    /// `&&`: run only if the previous pipeline succeeded
+    And,
+    /// `||`: run only if the previous pipeline failed
+    Or,
+}
+
+/// A pipeline together with the condition for running it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListItem {
+    /// The operator that precedes the pipeline
+    pub connector: Connector,
+    /// The pipeline to run
+    pub pipeline: Pipeline,
+    /// Whether the `&&`/`||` chain the pipeline belongs to ends with `&`
+    pub background: bool,
+}
+
+/// Pipelines sequenced with `&&`, `||`, `;` and `&`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommandList {
+    /// The pipelines in the order they run
+    pub items: Vec<ListItem>,
+}
+
+impl ListItem {
+    /// Whether the pipeline should run given the previous exit code
+    pub fn should_run(&self, last_exit_code: Option<i32>) -> bool {
+        match self.connector {
+            Connector::Always => true,
+            Connector::And => last_exit_code == Some(0),
+            Connector::Or => last_exit_code != Some(0),
+        }
+    }
+}
+
+impl fmt::Display for CommandList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, item) in self.items.iter().enumerate() {
+            if idx > 0 {
+                match item.connector {
+                    Connector::Always => write!(f, "; ")?,
+                    Connector::And => write!(f, " && ")?,
+                    Connector::Or => write!(f, " || ")?,
+                }
+            }
+            write!(f, "{}", item.pipeline)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, command) in self.commands.iter().enumerate() {
+            if idx > 0 {
+                write!(f, " | ")?;
+            }
+            write!(f, "{}", command.words.join(" "))?;
+
+            for redirect in &command.redirects {
+                match redirect {
+                    Redirect::Input(path) => write!(f, " < {}", path)?,
+                    Redirect::Output { path, append } => write!(f, " {} {}", if *append { ">>" } else { ">" }, path)?,
+                    Redirect::Error { path, append } => write!(f, " {} {}", if *append { "2>>" } else { "2>" }, path)?,
+                    Redirect::ErrorToOutput => write!(f, " 2>&1")?,
+                    Redirect::Both { path, append } => write!(f, " {} {}", if *append { "&>>" } else { "&>" }, path)?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Shell operators recognised by the lexer
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operator {
+    And,
+    Or,
+    Semicolon,
+    Background,
+    Pipe,
+    Input,
+    Output,
+    Append,
+    Error,
+    ErrorAppend,
+    ErrorToOutput,
+    Both,
+    BothAppend,
+}
+
+impl Operator {
+    /// Look up an operator token produced by the tokenizer
+    fn parse(text: &str) -> Self {
+        match text {
+            "&&" => Operator::And,
+            "||" => Operator::Or,
+            ";" => Operator::Semicolon,
+            "&" => Operator::Background,
+            "|" => Operator::Pipe,
+            "<" => Operator::Input,
+            ">" => Operator::Output,
+            ">>" => Operator::Append,
+            "2>" => Operator::Error,
+            "2>>" => Operator::ErrorAppend,
+            "2>&1" => Operator::ErrorToOutput,
+            "&>" => Operator::Both,
+            "&>>" => Operator::BothAppend,
+            _ => unreachable!("unknown operator token `{}`", text),
+        }
+    }
+
+    /// The operator as it is written on the command line
+    fn as_str(self) -> &'static str {
+        match self {
+            Operator::And => "&&",
+            Operator::Or => "||",
+            Operator::Semicolon => ";",
+            Operator::Background => "&",
+            Operator::Pipe => "|",
+            Operator::Input => "<",
+            Operator::Output => ">",
+            Operator::Append => ">>",
+            Operator::Error => "2>",
+            Operator::ErrorAppend => "2>>",
+            Operator::ErrorToOutput => "2>&1",
+            Operator::Both => "&>",
+            Operator::BothAppend => "&>>",
+        }
+    }
+}
+
+/// A lexical element of the command line
+#[derive(Debug, Clone, PartialEq)]
+enum Lexeme {
+    Word(String),
+    Op(Operator),
+}
+
+/// Split a command line into words and operators using the input tokenizer
+///
+/// Words keep their quotes, they are removed when the word is expanded.
+fn lex(input: &str) -> Result<Vec<Lexeme>, ParseError> {
+    let lexemes = input::tokenize(input)?
+        .into_iter()
+        .map(|token| match token.kind {
+            TokenKind::Word => Lexeme::Word(token.text),
+            TokenKind::Operator => Lexeme::Op(Operator::parse(&token.text)),
+        })
+        .collect();
+    Ok(lexemes)
+}
+
+/// Parse a command line into a list of pipelines
+pub fn parse_command_line(input: &str) -> Result<CommandList, ParseError> {
+    let mut list = CommandList::default();
+    let mut group = Vec::new();
+    let mut connector = Connector::Always;
+    let mut trailing = None;
+    // Index of the first item of the current `&&`/`||` chain
+    let mut chain_start = 0;
+
+    for lexeme in lex(input)? {
+        let op = match lexeme {
+            Lexeme::Op(op @ (Operator::And | Operator::Or | Operator::Semicolon | Operator::Background)) => op,
+            lexeme => {
+                group.push(lexeme);
+                trailing = None;
+                continue;
+            }
+        };
+
+        if group.is_empty() {
+            return Err(ParseError::UnexpectedOperator(op.as_str().to_string()));
+        }
+
+        list.items.push(ListItem {
+            connector,
+            pipeline: parse_pipeline(std::mem::take(&mut group))?,
+            background: false,
+        });
+        connector = match op {
+            Operator::And => Connector::And,
+            Operator::Or => Connector::Or,
+            _ => Connector::Always,
+        };
+        trailing = Some(op);
+
+        // `;` and `&` end the chain, `&` sending all of it to the background
+        if matches!(op, Operator::Semicolon | Operator::Background) {
+            if op == Operator::Background {
+                for item in &mut list.items[chain_start..] {
+                    item.background = true;
+                }
+            }
+            chain_start = list.items.len();
+        }
+    }
+
+    if group.is_empty() {
+        return match trailing {
+            // A trailing `;` or `&` just ends the last command
+            Some(Operator::Semicolon | Operator::Background) => Ok(list),
+            Some(_) => Err(ParseError::UnexpectedOperator("newline".to_string())),
+            None => Err(ParseError::EmptyCommand),
+        };
+    }
+
+    list.items.push(ListItem {
+        connector,
+        pipeline: parse_pipeline(group)?,
+        background: false,
+    });
+
+    Ok(list)
+}
+
+/// Parse the lexemes between list operators into a pipeline
+fn parse_pipeline(lexemes: Vec<Lexeme>) -> Result<Pipeline, ParseError> {
+    let mut pipeline = Pipeline::default();
+    let mut current = SimpleCommand::default();
+    let mut lexemes = lexemes.into_iter();
+
+    while let Some(lexeme) = lexemes.next() {
+        match lexeme {
+            Lexeme::Word(word) => current.words.push(word),
+            Lexeme::Op(Operator::Pipe) => {
+                if current.words.is_empty() {
+                    return Err(ParseError::UnexpectedOperator("|".to_string()));
+                }
+                pipeline.commands.push(std::mem::take(&mut current));
+            }
+            Lexeme::Op(Operator::ErrorToOutput) => current.redirects.push(Redirect::ErrorToOutput),
+            Lexeme::Op(op) => {
+                // Every other operator is a redirection that takes a file name
+                let path = match lexemes.next() {
+                    Some(Lexeme::Word(path)) => path,
+                    _ => return Err(ParseError::MissingRedirectTarget(op.as_str().to_string())),
+                };
+
+                current.redirects.push(match op {
+                    Operator::Input => Redirect::Input(path),
+                    Operator::Output => Redirect::Output { path, append: false },
+                    Operator::Append => Redirect::Output { path, append: true },
+                    Operator::Error => Redirect::Error { path, append: false },
+                    Operator::ErrorAppend => Redirect::Error { path, append: true },
+                    Operator::Both => Redirect::Both { path, append: false },
+                    Operator::BothAppend => Redirect::Both { path, append: true },
+                    _ => unreachable!(),
+                });
+            }
+        }
+    }
+
+    if current.words.is_empty() {
+        // A trailing pipe or nothing but redirections
+        return Err(ParseError::UnexpectedOperator("newline".to_string()));
+    }
+    pipeline.commands.push(current);
+
+    Ok(pipeline)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse a line that is expected to hold a single pipeline
+    fn parse_single(input: &str) -> Pipeline {
+        let mut list = parse_command_line(input).unwrap();
+        assert_eq!(list.items.len(), 1);
+        list.items.remove(0).pipeline
+    }
+
+    #[test]
+    fn test_parse_pipeline() {
+        let pipeline = parse_single("ps aux | grep foo|wc -l");
+
+        assert_eq!(pipeline.commands.len(), 3);
+        assert_eq!(pipeline.commands[0].words, vec!["ps", "aux"]);
+        assert_eq!(pipeline.commands[1].words, vec!["grep", "foo"]);
+        assert_eq!(pipeline.commands[2].words, vec!["wc", "-l"]);
+    }
+
+    #[test]
+    fn test_parse_redirections() {
+        let pipeline = parse_single("sort < in.txt 2>&1 >> out.txt 2>err.log");
+        let command = &pipeline.commands[0];
+
+        assert_eq!(command.words, vec!["sort"]);
+        assert_eq!(command.redirects, vec![
+            Redirect::Input("in.txt".to_string()),
+            Redirect::ErrorToOutput,
+            Redirect::Output { path: "out.txt".to_string(), append: true },
+            Redirect::Error { path: "err.log".to_string(), append: false },
+        ]);
+    }
+
+    #[test]
+    fn test_parse_command_list() {
+        let list = parse_command_line("cargo build && cargo test || echo failed; ls;").unwrap();
+        let connectors: Vec<Connector> = list.items.iter().map(|item| item.connector).collect();
+        let commands: Vec<String> = list.items.iter().map(|item| item.pipeline.to_string()).collect();
+
+        assert_eq!(connectors, vec![Connector::Always, Connector::And, Connector::Or, Connector::Always]);
+        assert_eq!(commands, vec!["cargo build", "cargo test", "echo failed", "ls"]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse_command_line("   "), Err(ParseError::EmptyCommand));
+        assert_eq!(parse_command_line("| grep foo"), Err(ParseError::UnexpectedOperator("|".to_string())));
+        assert_eq!(parse_command_line("ls |"), Err(ParseError::UnexpectedOperator("newline".to_string())));
+        assert_eq!(parse_command_line("ls &>"), Err(ParseError::MissingRedirectTarget("&>".to_string())));
+        assert_eq!(parse_command_line("ls &&"), Err(ParseError::UnexpectedOperator("newline".to_string())));
+        assert_eq!(parse_command_line("; ls"), Err(ParseError::UnexpectedOperator(";".to_string())));
+        assert_eq!(parse_command_line("& ls"), Err(ParseError::UnexpectedOperator("&".to_string())));
+    }
+
+    #[test]
+    fn test_parse_background() {
+        let list = parse_command_line("make && make install & sleep 1; ls &").unwrap();
+        let background: Vec<bool> = list.items.iter().map(|item| item.background).collect();
+
+        assert_eq!(background, vec![true, true, false, true]);
+        assert_eq!(list.items[2].connector, Connector::Always);
+        assert_eq!(list.to_string(), "make && make install; sleep 1; ls");
+    }
+}
@@ -0,0 +1,345 @@
+use std::io;
+use std::process::{Child, Command, ExitStatus};
+use std::time::{Duration, Instant};
+
+/// Signals mouse_term sends to running commands
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Signal {
+    /// SIGINT, what Ctrl+C sends in a shell
+    Interrupt,
+    /// SIGTERM, a request to exit that the command may still handle
+    Terminate,
+    /// SIGKILL, which cannot be caught
+    Kill,
+    /// SIGTSTP, what Ctrl+Z sends in a shell
+    Suspend,
+    /// SIGSTOP, which stops the command without asking
+    Stop,
+    /// SIGCONT, resuming a stopped command
+    Continue,
+}
+
+impl Signal {
+    /// The harsher signal to try when this one did not stop the command
+    pub fn escalate(self) -> Self {
+        match self {
+            Signal::Interrupt => Signal::Terminate,
+            Signal::Terminate | Signal::Kill => Signal::Kill,
+            // Stopping and resuming don't count towards ending the command
+            Signal::Suspend | Signal::Stop | Signal::Continue => Signal::Interrupt,
+        }
+    }
+
+    /// The conventional name of the signal
+    pub fn name(self) -> &'static str {
+        match self {
+            Signal::Interrupt => "SIGINT",
+            Signal::Terminate => "SIGTERM",
+            Signal::Kill => "SIGKILL",
+            Signal::Suspend => "SIGTSTP",
+            Signal::Stop => "SIGSTOP",
+            Signal::Continue => "SIGCONT",
+        }
+    }
+
+    /// Parse a signal as given to `kill`, by name with or without the `SIG`
+    /// prefix, or by its usual number
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_uppercase();
+        match name.strip_prefix("SIG").unwrap_or(&name) {
+            "INT" | "2" => Some(Signal::Interrupt),
+            "TERM" | "15" => Some(Signal::Terminate),
+            "KILL" | "9" => Some(Signal::Kill),
+            "TSTP" | "20" => Some(Signal::Suspend),
+            "STOP" | "19" => Some(Signal::Stop),
+            "CONT" | "18" => Some(Signal::Continue),
+            _ => None,
+        }
+    }
+
+    /// Whether the signal stops the command rather than ending it
+    pub fn stops(self) -> bool {
+        matches!(self, Signal::Suspend | Signal::Stop)
+    }
+
+    /// The number of the signal on this platform
+    #[cfg(unix)]
+    fn number(self) -> libc::c_int {
+        match self {
+            Signal::Interrupt => libc::SIGINT,
+            Signal::Terminate => libc::SIGTERM,
+            Signal::Kill => libc::SIGKILL,
+            Signal::Suspend => libc::SIGTSTP,
+            Signal::Stop => libc::SIGSTOP,
+            Signal::Continue => libc::SIGCONT,
+        }
+    }
+}
+
+/// Resources a finished command used
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ResourceUsage {
+    /// Wall-clock time from start to exit
+    pub wall: Duration,
+    /// CPU time spent running the command's own code
+    pub user: Duration,
+    /// CPU time the kernel spent on the command's behalf
+    pub system: Duration,
+    /// Peak resident set size in kilobytes
+    pub max_rss_kb: u64,
+}
+
+impl ResourceUsage {
+    /// Combine the usage of two processes that ran side by side
+    ///
+    /// CPU times add up, while memory peaks are not known to overlap, so
+    /// the larger one is kept.
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            wall: self.wall.max(other.wall),
+            user: self.user + other.user,
+            system: self.system + other.system,
+            max_rss_kb: self.max_rss_kb.max(other.max_rss_kb),
+        }
+    }
+
+    /// Convert the rusage reported by the kernel
+    #[cfg(unix)]
+    fn from_rusage(usage: &libc::rusage) -> Self {
+        let time = |tv: libc::timeval| Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000);
+
+        // macOS reports the peak in bytes, Linux in kilobytes
+        let max_rss = usage.ru_maxrss as u64;
+        let max_rss_kb = if cfg!(target_os = "macos") { max_rss / 1024 } else { max_rss };
+
+        Self {
+            wall: Duration::ZERO,
+            user: time(usage.ru_utime),
+            system: time(usage.ru_stime),
+            max_rss_kb,
+        }
+    }
+}
+
+/// Whether [`wait_exited`] can wait for a child without reaping it
+pub const CAN_WAIT_EXITED: bool = cfg!(unix);
+
+/// Block until a child has exited, leaving it to be reaped
+///
+/// A pid isn't reused until it is reaped, so the child's process group can
+/// still be signalled safely until then.
+#[cfg(unix)]
+pub fn wait_exited(pid: u32) -> io::Result<()> {
+    loop {
+        // SAFETY: siginfo_t is plain data that waitid fills in
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        // SAFETY: waiting on a child we spawned, with a valid out pointer;
+        // WNOWAIT leaves it to be reaped
+        if unsafe { libc::waitid(libc::P_PID, pid as libc::id_t, &mut info, libc::WEXITED | libc::WNOWAIT) } == -1 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        return Ok(());
+    }
+}
+
+/// Block until a child has exited, leaving it to be reaped
+#[cfg(not(unix))]
+pub fn wait_exited(_pid: u32) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "waiting without reaping is not supported on this platform"))
+}
+
+/// Check whether a child has exited without blocking, collecting its resource usage
+///
+/// The child is reaped here, so it must not be waited on through `Child` afterwards.
+#[cfg(unix)]
+pub fn try_wait_usage(child: &mut Child) -> io::Result<Option<(ExitStatus, ResourceUsage)>> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let mut status = 0;
+    // SAFETY: rusage is plain data that wait4 fills in
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    // SAFETY: waiting on a child we spawned and have not reaped, with valid out pointers
+    match unsafe { libc::wait4(child.id() as libc::pid_t, &mut status, libc::WNOHANG, &mut usage) } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(None),
+        _ => Ok(Some((ExitStatus::from_raw(status), ResourceUsage::from_rusage(&usage)))),
+    }
+}
+
+/// Check whether a child has exited without blocking
+///
+/// Resource usage is not available on this platform.
+#[cfg(not(unix))]
+pub fn try_wait_usage(child: &mut Child) -> io::Result<Option<(ExitStatus, ResourceUsage)>> {
+    Ok(child.try_wait()?.map(|status| (status, ResourceUsage::default())))
+}
+
+/// Get the signal that killed a process, if it did not exit normally
+#[cfg(unix)]
+pub fn exit_signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+
+    status.signal()
+}
+
+/// Get the signal that killed a process, if it did not exit normally
+#[cfg(not(unix))]
+pub fn exit_signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+/// Get the conventional name of a signal number, such as `SIGKILL` for 9
+pub fn signal_name(signum: i32) -> String {
+    #[cfg(unix)]
+    let name = match signum {
+        libc::SIGHUP => Some("SIGHUP"),
+        libc::SIGINT => Some("SIGINT"),
+        libc::SIGQUIT => Some("SIGQUIT"),
+        libc::SIGILL => Some("SIGILL"),
+        libc::SIGABRT => Some("SIGABRT"),
+        libc::SIGBUS => Some("SIGBUS"),
+        libc::SIGFPE => Some("SIGFPE"),
+        libc::SIGKILL => Some("SIGKILL"),
+        libc::SIGSEGV => Some("SIGSEGV"),
+        libc::SIGPIPE => Some("SIGPIPE"),
+        libc::SIGALRM => Some("SIGALRM"),
+        libc::SIGTERM => Some("SIGTERM"),
+        libc::SIGUSR1 => Some("SIGUSR1"),
+        libc::SIGUSR2 => Some("SIGUSR2"),
+        libc::SIGXCPU => Some("SIGXCPU"),
+        _ => None,
+    };
+    #[cfg(not(unix))]
+    let name: Option<&str> = None;
+
+    name.map_or_else(|| format!("signal {}", signum), str::to_string)
+}
+
+/// Start a command as the leader of a new process group
+#[cfg(unix)]
+pub fn new_group(cmd: &mut Command) {
+    use std::os::unix::process::CommandExt;
+
+    cmd.process_group(0);
+}
+
+/// Start a command as the leader of a new process group
+#[cfg(not(unix))]
+pub fn new_group(_cmd: &mut Command) {}
+
+/// Send a signal to the process group led by a child
+///
+/// Children are started as group leaders, so this also reaches anything
+/// they spawned themselves. The child must not be reaped yet, or its pid
+/// may belong to another process by now.
+#[cfg(unix)]
+pub fn signal_group(child: &Child, signal: Signal) -> io::Result<()> {
+    // SAFETY: killpg has no memory safety requirements
+    if unsafe { libc::killpg(child.id() as libc::pid_t, signal.number()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Send a signal to the process group led by a child
+#[cfg(not(unix))]
+pub fn signal_group(_child: &Child, _signal: Signal) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "signals are not supported on this platform"))
+}
+
+/// Send a signal to a process by its pid, like `kill PID`
+#[cfg(unix)]
+pub fn signal_pid(pid: i32, signal: Signal) -> io::Result<()> {
+    // SAFETY: kill has no memory safety requirements
+    if unsafe { libc::kill(pid as libc::pid_t, signal.number()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Send a signal to a process by its pid, like `kill PID`
+#[cfg(not(unix))]
+pub fn signal_pid(_pid: i32, _signal: Signal) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "signals are not supported on this platform"))
+}
+
+/// Start a command in its own process group, in the foreground of our terminal
+///
+/// Keys such as Ctrl+C then signal the command instead of mouse_term.
+#[cfg(unix)]
+pub fn foreground(cmd: &mut Command) {
+    use std::os::unix::process::CommandExt;
+
+    // SAFETY: only async-signal-safe libc calls are made between fork and exec
+    unsafe {
+        cmd.pre_exec(|| {
+            libc::setpgid(0, 0);
+            // Taking the terminal from the background raises SIGTTOU
+            libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+            libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpid());
+            libc::signal(libc::SIGTTOU, libc::SIG_DFL);
+            Ok(())
+        });
+    }
+}
+
+/// Wait for a foreground command to exit, then take the terminal back
+///
+/// There is no shell to resume a suspended program from, so one that stops
+/// itself (Ctrl+Z in vim) is simply continued.
+#[cfg(unix)]
+pub fn wait_foreground(child: &mut Child) -> io::Result<(ExitStatus, ResourceUsage)> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let started = Instant::now();
+    let pid = child.id() as libc::pid_t;
+    let result = loop {
+        let mut status = 0;
+        // SAFETY: rusage is plain data that wait4 fills in
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        // SAFETY: waiting on a child we spawned and have not reaped, with valid out pointers
+        if unsafe { libc::wait4(pid, &mut status, libc::WUNTRACED, &mut usage) } == -1 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            break Err(err);
+        }
+
+        if libc::WIFSTOPPED(status) {
+            // SAFETY: signalling the process group of our own child
+            unsafe { libc::killpg(pid, libc::SIGCONT) };
+            continue;
+        }
+        let usage = ResourceUsage {
+            wall: started.elapsed(),
+            ..ResourceUsage::from_rusage(&usage)
+        };
+        break Ok((ExitStatus::from_raw(status), usage));
+    };
+
+    // SAFETY: plain libc calls on our own terminal and signal disposition
+    unsafe {
+        let previous = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+        libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp());
+        libc::signal(libc::SIGTTOU, previous);
+    }
+
+    result
+}
+
+/// Start a command in the foreground of our terminal
+#[cfg(not(unix))]
+pub fn foreground(_cmd: &mut Command) {}
+
+/// Wait for a foreground command to exit
+#[cfg(not(unix))]
+pub fn wait_foreground(child: &mut Child) -> io::Result<(ExitStatus, ResourceUsage)> {
+    let started = Instant::now();
+    let status = child.wait()?;
+    Ok((status, ResourceUsage { wall: started.elapsed(), ..ResourceUsage::default() }))
+}
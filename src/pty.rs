@@ -0,0 +1,152 @@
+use std::fs::File;
+use std::io;
+use std::process::Command;
+
+/// A pseudo-terminal pair used to run a command as if it had a real terminal
+pub struct Pty {
+    /// The controlling side, read by us to capture output
+    master: File,
+    /// The terminal side, handed to the child as stdin/stdout/stderr
+    slave: File,
+}
+
+#[cfg(unix)]
+impl Pty {
+    /// Allocate a new pty pair with the given window size
+    pub fn open(rows: u16, cols: u16) -> io::Result<Self> {
+        use std::os::fd::FromRawFd;
+
+        let mut master: libc::c_int = -1;
+        let mut slave: libc::c_int = -1;
+        let mut size = window_size(rows, cols);
+
+        // SAFETY: all pointers are valid for the duration of the call
+        let ret = unsafe {
+            libc::openpty(&mut master, &mut slave, std::ptr::null_mut(), std::ptr::null_mut(), &raw mut size)
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: openpty succeeded, so both descriptors are open and owned by us
+        let (master, slave) = unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) };
+
+        // Keep the master out of spawned children
+        set_cloexec(&master)?;
+        set_cloexec(&slave)?;
+
+        Ok(Self { master, slave })
+    }
+
+    /// Get the master side of the pty
+    pub fn master(&self) -> &File {
+        &self.master
+    }
+
+    /// Wire a command up to the terminal side of the pty
+    ///
+    /// The child becomes a session leader with the pty as its controlling
+    /// terminal, so job-control aware programs behave as they do in a shell.
+    pub fn attach(&self, cmd: &mut Command) -> io::Result<()> {
+        use std::os::unix::process::CommandExt;
+
+        cmd.stdin(self.slave.try_clone()?)
+            .stdout(self.slave.try_clone()?)
+            .stderr(self.slave.try_clone()?);
+
+        // SAFETY: only async-signal-safe libc calls are made between fork and exec
+        unsafe {
+            cmd.pre_exec(|| {
+                if libc::setsid() == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                // Failing to acquire the controlling terminal is not fatal,
+                // the child still sees a tty on its standard streams
+                libc::ioctl(0, libc::TIOCSCTTY, 0);
+                Ok(())
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Get the terminal side of the pty
+    ///
+    /// Reads from the master only report end of file after every copy of the
+    /// terminal side has been closed.
+    pub fn slave(&self) -> &File {
+        &self.slave
+    }
+}
+
+#[cfg(not(unix))]
+impl Pty {
+    /// Pseudo-terminals are only supported on Unix platforms
+    pub fn open(_rows: u16, _cols: u16) -> io::Result<Self> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "pseudo-terminals are not supported on this platform"))
+    }
+
+    /// Get the master side of the pty
+    pub fn master(&self) -> &File {
+        &self.master
+    }
+
+    /// Wire a command up to the terminal side of the pty
+    pub fn attach(&self, _cmd: &mut Command) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "pseudo-terminals are not supported on this platform"))
+    }
+
+    /// Get the terminal side of the pty
+    pub fn slave(&self) -> &File {
+        &self.slave
+    }
+}
+
+/// Resize the terminal behind a pty master
+///
+/// The kernel delivers SIGWINCH to the foreground process group so full
+/// screen programs can redraw at the new size.
+#[cfg(unix)]
+pub fn resize(master: &File, rows: u16, cols: u16) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let size = window_size(rows, cols);
+    // SAFETY: the descriptor is open and `size` is a valid winsize
+    if unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &size) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Resize the terminal behind a pty master
+#[cfg(not(unix))]
+pub fn resize(_master: &File, _rows: u16, _cols: u16) -> io::Result<()> {
+    Ok(())
+}
+
+/// Build a winsize for the given dimensions, never reporting a zero size
+#[cfg(unix)]
+fn window_size(rows: u16, cols: u16) -> libc::winsize {
+    libc::winsize {
+        ws_row: rows.max(1),
+        ws_col: cols.max(1),
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+/// Mark a descriptor close-on-exec
+#[cfg(unix)]
+fn set_cloexec(file: &File) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let fd = file.as_raw_fd();
+    // SAFETY: fcntl on an open descriptor we own
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        if flags == -1 || libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
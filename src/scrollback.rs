@@ -0,0 +1,277 @@
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+
+use crate::executor::{OutputLine, Stream};
+
+/// Lines of a job's output kept in memory, older ones are moved to a temporary file
+const MEMORY_LINES: usize = 10_000;
+
+/// Most lines moved to the temporary file at once
+const SPILL_BATCH: usize = 1_000;
+
+/// Lines between the file offsets remembered for finding a spilled line
+const INDEX_STRIDE: usize = 256;
+
+/// Numbers the temporary files of this process
+static SPILL_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// The output of a job, with the newest lines in memory and older ones on disk
+///
+/// Lines are numbered from the first line of output wherever they are kept,
+/// so the output pane can page through all of it. Only lines still in
+/// memory can change, such as a progress bar being redrawn.
+#[derive(Debug)]
+pub struct Scrollback {
+    /// The newest lines
+    recent: VecDeque<OutputLine>,
+    /// How many lines are kept in memory
+    capacity: usize,
+    /// How many lines were moved out of memory
+    spilled: usize,
+    /// The file older lines are moved to, once there are any
+    spill: Option<SpillFile>,
+    /// How many of the spilled lines are in the file, the rest were lost to a write error
+    on_disk: usize,
+    /// Whether writing to the file failed, so older lines are now dropped
+    spill_failed: bool,
+}
+
+impl Default for Scrollback {
+    fn default() -> Self {
+        Self::with_capacity(MEMORY_LINES)
+    }
+}
+
+impl Scrollback {
+    /// Create a scrollback keeping `capacity` lines in memory
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            recent: VecDeque::new(),
+            capacity: capacity.max(1),
+            spilled: 0,
+            spill: None,
+            on_disk: 0,
+            spill_failed: false,
+        }
+    }
+
+    /// Get the number of lines of output
+    pub fn len(&self) -> usize {
+        self.spilled + self.recent.len()
+    }
+
+    /// Add a line, moving the oldest lines to disk once memory is full
+    pub fn push(&mut self, line: OutputLine) {
+        self.recent.push_back(line);
+        if self.recent.len() > self.capacity {
+            self.spill_oldest();
+        }
+    }
+
+    /// Get a line that can still be changed, which it can while in memory
+    pub fn get_mut(&mut self, idx: usize) -> Option<&mut OutputLine> {
+        let idx = idx.checked_sub(self.spilled)?;
+        self.recent.get_mut(idx)
+    }
+
+    /// Get a range of lines, reading older ones back from disk
+    ///
+    /// Lines that could not be written to or read from disk are left out.
+    pub fn lines(&self, range: Range<usize>) -> Vec<OutputLine> {
+        let end = range.end.min(self.len());
+        let start = range.start.min(end);
+        let mut lines = Vec::with_capacity(end - start);
+
+        if let Some(spill) = &self.spill
+            && start < self.on_disk
+        {
+            lines.extend(spill.read(start..end.min(self.on_disk)).unwrap_or_default());
+        }
+
+        let memory_start = start.max(self.spilled) - self.spilled;
+        let memory_end = end.max(self.spilled) - self.spilled;
+        lines.extend(self.recent.range(memory_start..memory_end).cloned());
+        lines
+    }
+
+    /// Move a batch of the oldest lines from memory to the temporary file
+    fn spill_oldest(&mut self) {
+        let count = (self.capacity / 10).clamp(1, SPILL_BATCH);
+        let lines: Vec<OutputLine> = self.recent.drain(..count).collect();
+        let first = self.spilled;
+        self.spilled += count;
+
+        if self.spill_failed {
+            return;
+        }
+        if self.spill.is_none() {
+            self.spill = SpillFile::create().ok();
+        }
+        let written = match &mut self.spill {
+            Some(spill) => spill.write(first, &lines).is_ok(),
+            None => false,
+        };
+
+        // Once a write fails the file is missing lines, so stop using it
+        if written {
+            self.on_disk = self.spilled;
+        } else {
+            self.spill_failed = true;
+        }
+    }
+}
+
+impl Extend<OutputLine> for Scrollback {
+    fn extend<I: IntoIterator<Item = OutputLine>>(&mut self, lines: I) {
+        for line in lines {
+            self.push(line);
+        }
+    }
+}
+
+/// A temporary file holding the oldest lines of a job's output
+///
+/// Each line is stored as its stream, arrival time in nanoseconds and
+/// length, then its text.
+#[derive(Debug)]
+struct SpillFile {
+    file: File,
+    path: PathBuf,
+    /// Offset of every `INDEX_STRIDE`th line
+    index: Vec<u64>,
+    /// Length of the file
+    len: u64,
+}
+
+impl SpillFile {
+    /// Create a new temporary file only we can read
+    fn create() -> io::Result<Self> {
+        let path = std::env::temp_dir().join(format!(
+            "mouse_term-{}-{}.out",
+            std::process::id(),
+            SPILL_FILES.fetch_add(1, Ordering::Relaxed),
+        ));
+
+        let mut options = OpenOptions::new();
+        options.read(true).append(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let file = options.open(&path)?;
+
+        // Where open files can be removed, the file goes away even if
+        // mouse_term doesn't exit cleanly
+        let _ = fs::remove_file(&path);
+
+        Ok(Self {
+            file,
+            path,
+            index: Vec::new(),
+            len: 0,
+        })
+    }
+
+    /// Append lines, the first of which is line `first` of the file
+    fn write(&mut self, first: usize, lines: &[OutputLine]) -> io::Result<()> {
+        let mut buffer = Vec::new();
+        for (number, line) in (first..).zip(lines) {
+            if number.is_multiple_of(INDEX_STRIDE) {
+                self.index.push(self.len + buffer.len() as u64);
+            }
+
+            let time = line.time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+            buffer.push(line.stream as u8);
+            buffer.extend_from_slice(&(time.as_nanos() as u64).to_le_bytes());
+            buffer.extend_from_slice(&(line.text.len() as u32).to_le_bytes());
+            buffer.extend_from_slice(line.text.as_bytes());
+        }
+
+        (&self.file).write_all(&buffer)?;
+        self.len += buffer.len() as u64;
+        Ok(())
+    }
+
+    /// Read back a range of lines, all of which are in the file
+    fn read(&self, range: Range<usize>) -> io::Result<Vec<OutputLine>> {
+        let indexed = range.start / INDEX_STRIDE;
+        let offset = self.index.get(indexed).copied().unwrap_or_default();
+
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut reader = BufReader::new(file);
+
+        let mut lines = Vec::with_capacity(range.len());
+        for number in indexed * INDEX_STRIDE..range.end {
+            let line = Self::read_line(&mut reader)?;
+            if number >= range.start {
+                lines.push(line);
+            }
+        }
+        Ok(lines)
+    }
+
+    /// Read the line at the reader's position
+    fn read_line(reader: &mut impl Read) -> io::Result<OutputLine> {
+        let mut stream = [0; 1];
+        let mut time = [0; 8];
+        let mut len = [0; 4];
+        reader.read_exact(&mut stream)?;
+        reader.read_exact(&mut time)?;
+        reader.read_exact(&mut len)?;
+
+        let mut text = vec![0; u32::from_le_bytes(len) as usize];
+        reader.read_exact(&mut text)?;
+
+        Ok(OutputLine {
+            stream: if stream[0] == Stream::Stderr as u8 { Stream::Stderr } else { Stream::Stdout },
+            text: String::from_utf8_lossy(&text).into_owned(),
+            time: SystemTime::UNIX_EPOCH + Duration::from_nanos(u64::from_le_bytes(time)),
+        })
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spill_to_disk() {
+        let mut scrollback = Scrollback::with_capacity(20);
+        for n in 0..1000 {
+            let stream = if n % 3 == 0 { Stream::Stderr } else { Stream::Stdout };
+            scrollback.push(OutputLine::new(stream, format!("line {} ✓", n)));
+        }
+        assert_eq!(scrollback.len(), 1000);
+        assert!(scrollback.recent.len() <= 20);
+        assert_eq!(scrollback.on_disk, scrollback.spilled);
+
+        // Lines come back from disk and memory alike, across an index stride
+        let lines = scrollback.lines(250..990);
+        assert_eq!(lines.len(), 740);
+        for (n, line) in (250..).zip(&lines) {
+            assert_eq!(line.text, format!("line {} ✓", n));
+            assert_eq!(line.stream == Stream::Stderr, n % 3 == 0);
+        }
+        assert_eq!(scrollback.lines(995..2000).len(), 5);
+
+        // Only lines still in memory can change
+        assert!(scrollback.get_mut(0).is_none());
+        if let Some(line) = scrollback.get_mut(999) {
+            line.text = "last".to_string();
+        }
+        assert_eq!(scrollback.lines(999..1000)[0].text, "last");
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::executor::{OutputLine, Stream};
+use crate::expand;
+
+/// Variables the shell sets for itself, which aren't part of the session
+const SHELL_OWN_VARS: &[&str] = &["_", "SHLVL"];
+
+/// The directory and environment a shell was left with
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShellState {
+    /// The shell's working directory
+    pub cwd: PathBuf,
+    /// The shell's exported variables
+    pub env: HashMap<String, String>,
+}
+
+/// Picks out the state a shell reports after a command line
+///
+/// The command line runs with a few lines added that print a marker and
+/// then `pwd` and `env`. The marker and everything after it are kept out
+/// of the job's output, along with the blank line printed before it, which
+/// ends output that didn't end with a newline. All of them are on stdout, so
+/// stderr is passed through untouched, even when it arrives after the marker.
+#[derive(Debug)]
+pub struct ShellCapture {
+    /// A line the command's own output won't contain
+    marker: String,
+    /// A blank line held back until it is clear it isn't the marker's
+    held_blank: Option<OutputLine>,
+    /// Lines after the marker, once it was seen
+    report: Option<Vec<String>>,
+}
+
+impl Default for ShellCapture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShellCapture {
+    /// Create a capture with a marker of its own
+    pub fn new() -> Self {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+        Self {
+            marker: format!("__mouse_term_state_{}_{}__", std::process::id(), nanos),
+            held_blank: None,
+            report: None,
+        }
+    }
+
+    /// Build the script handing a command line to the shell, keeping its exit code
+    pub fn script(&self, command: &str) -> String {
+        format!(
+            "{}\n__mouse_term_status=$?\nprintf '\\n%s\\n' '{}'\npwd\nenv\nexit $__mouse_term_status\n",
+            command, self.marker,
+        )
+    }
+
+    /// Filter a complete line of output
+    ///
+    /// Returns the lines to show, each with whether it is complete.
+    pub fn line(&mut self, line: OutputLine) -> Vec<(OutputLine, bool)> {
+        if line.stream == Stream::Stderr {
+            return vec![(line, true)];
+        }
+        if let Some(report) = &mut self.report {
+            report.push(line.text);
+            return Vec::new();
+        }
+        if line.text == self.marker {
+            self.held_blank = None;
+            self.report = Some(Vec::new());
+            return Vec::new();
+        }
+
+        let mut lines: Vec<(OutputLine, bool)> = self.held_blank.take().map(|blank| (blank, true)).into_iter().collect();
+        if line.text.is_empty() {
+            self.held_blank = Some(line);
+        } else {
+            lines.push((line, true));
+        }
+        lines
+    }
+
+    /// Filter a line still being written, leaving out the start of the
+    /// marker or of what follows it
+    ///
+    /// Returns the lines to show, each with whether it is complete.
+    pub fn partial(&mut self, line: OutputLine) -> Vec<(OutputLine, bool)> {
+        if line.stream == Stream::Stderr {
+            return vec![(line, false)];
+        }
+        if self.report.is_some() || (!line.text.is_empty() && self.marker.starts_with(&line.text)) {
+            return Vec::new();
+        }
+        let blank = self.held_blank.take().map(|blank| (blank, true));
+        blank.into_iter().chain([(line, false)]).collect()
+    }
+
+    /// Finish the capture once the shell has exited
+    ///
+    /// Returns a blank line still held back, and the state the shell
+    /// reported, which is missing when it exited before the marker.
+    pub fn finish(self) -> (Option<OutputLine>, Option<ShellState>) {
+        let state = self.report.and_then(|report| {
+            let mut lines = report.into_iter();
+            let cwd = PathBuf::from(lines.next()?);
+            if !cwd.is_absolute() {
+                return None;
+            }
+            Some(ShellState { cwd, env: parse_env(lines) })
+        });
+        (self.held_blank, state)
+    }
+}
+
+/// Parse the output of `env`
+///
+/// A line that doesn't start with a variable name and `=` belongs to the
+/// value before it, which had a newline in it.
+fn parse_env(lines: impl Iterator<Item = String>) -> HashMap<String, String> {
+    let mut env: Vec<(String, String)> = Vec::new();
+    for line in lines {
+        let name = line.split_once('=').map(|(name, _)| name).filter(|name| expand::is_valid_name(name));
+        match (name, env.last_mut()) {
+            (Some(name), _) => {
+                let value = line[name.len() + 1..].to_string();
+                env.push((name.to_string(), value));
+            }
+            (None, Some((_, value))) => {
+                value.push('\n');
+                value.push_str(&line);
+            }
+            (None, None) => {}
+        }
+    }
+
+    env.into_iter()
+        .filter(|(name, _)| !SHELL_OWN_VARS.contains(&name.as_str()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shell_capture() {
+        let mut capture = ShellCapture::new();
+        let marker = capture.marker.clone();
+        let mut shown = Vec::new();
+        let mut feed = |capture: &mut ShellCapture, text: &str| {
+            shown.extend(capture.line(OutputLine::new(Stream::Stdout, text)).into_iter().map(|(line, _)| line.text));
+        };
+
+        // Blank lines of the command's own are kept, the one before the marker isn't
+        for text in ["one", "", "", "two", ""] {
+            feed(&mut capture, text);
+        }
+        assert!(capture.partial(OutputLine::new(Stream::Stdout, &marker[..10])).is_empty());
+        for text in [marker.as_str(), "/tmp/project", "HOME=/home/me", "SHLVL=2", "NOTE=first", "second", "_=/usr/bin/env"] {
+            feed(&mut capture, text);
+        }
+        assert_eq!(shown, ["one", "", "", "two"]);
+
+        let (blank, state) = capture.finish();
+        assert!(blank.is_none());
+        let state = state.unwrap();
+        assert_eq!(state.cwd, PathBuf::from("/tmp/project"));
+        assert_eq!(state.env.len(), 2);
+        assert_eq!(state.env["HOME"], "/home/me");
+        assert_eq!(state.env["NOTE"], "first\nsecond");
+
+        // Stderr is shown wherever it arrives, and never taken for the report
+        let mut capture = ShellCapture::new();
+        let marker = capture.marker.clone();
+        let stderr = |text: &str| OutputLine::new(Stream::Stderr, text);
+        let mut shown = Vec::new();
+        for line in [
+            OutputLine::new(Stream::Stdout, ""),
+            stderr(""),
+            OutputLine::new(Stream::Stdout, marker.as_str()),
+            stderr("warning: late"),
+            OutputLine::new(Stream::Stdout, "/tmp"),
+            stderr("relative/dir"),
+            OutputLine::new(Stream::Stdout, "HOME=/home/me"),
+        ] {
+            shown.extend(capture.line(line).into_iter().map(|(line, _)| (line.stream, line.text)));
+        }
+        let expected = ["", "warning: late", "relative/dir"].map(|text| (Stream::Stderr, text.to_string()));
+        assert_eq!(shown, expected);
+        let (blank, state) = capture.finish();
+        assert!(blank.is_none());
+        let state = state.unwrap();
+        assert_eq!(state.cwd, PathBuf::from("/tmp"));
+        assert_eq!(state.env.len(), 1);
+
+        // A directory that isn't absolute isn't one the shell reported
+        let mut capture = ShellCapture::new();
+        let marker = capture.marker.clone();
+        for text in [marker.as_str(), "relative/dir"] {
+            capture.line(OutputLine::new(Stream::Stdout, text));
+        }
+        assert!(capture.finish().1.is_none());
+
+        // A shell that exits early reports nothing
+        let mut capture = ShellCapture::new();
+        assert!(capture.line(OutputLine::new(Stream::Stdout, "")).is_empty());
+        let (blank, state) = capture.finish();
+        assert!(blank.is_some());
+        assert!(state.is_none());
+    }
+}
@@ -0,0 +1,336 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use zeroize::{Zeroize, Zeroizing};
+
+/// Environment variable naming the socket to fetch a password from, set
+/// when sudo runs mouse_term as its askpass program
+pub const ASKPASS_SOCKET_VAR: &str = "MOUSE_TERM_ASKPASS_SOCKET";
+
+/// Bytes reserved for a password, so typing one doesn't move it around memory
+const SECRET_CAPACITY: usize = 128;
+
+/// sudo options that take a value, which may be the next word
+const VALUE_OPTIONS: &[char] = &['u', 'g', 'C', 'D', 'h', 'p', 'r', 't', 'U'];
+
+/// Long sudo options that take a value, when it isn't given with `=`
+const LONG_VALUE_OPTIONS: &[&str] = &[
+    "--user", "--group", "--close-from", "--chdir", "--host", "--prompt", "--role", "--type", "--other-user",
+];
+
+/// Variables pointing sudo at the askpass helper
+pub const ASKPASS_VARS: &[&str] = &["SUDO_ASKPASS", ASKPASS_SOCKET_VAR];
+
+/// Numbers the askpass sockets of this process
+static ASKPASS_SOCKETS: AtomicUsize = AtomicUsize::new(0);
+
+/// A password, wiped from memory when it is cleared or dropped
+pub struct Secret(Zeroizing<String>);
+
+impl Default for Secret {
+    fn default() -> Self {
+        Self(Zeroizing::new(String::with_capacity(SECRET_CAPACITY)))
+    }
+}
+
+impl Secret {
+    /// Add a typed character
+    pub fn push(&mut self, c: char) {
+        // Growing the string would leave the old copy behind, so copy it
+        // by hand and wipe the old one
+        if self.0.len() + c.len_utf8() > self.0.capacity() {
+            let mut grown = String::with_capacity(self.0.capacity() * 2);
+            grown.push_str(&self.0);
+            self.0 = Zeroizing::new(grown);
+        }
+        self.0.push(c);
+    }
+
+    /// Remove the last character
+    pub fn pop(&mut self) {
+        self.0.pop();
+    }
+
+    /// Wipe the password
+    pub fn clear(&mut self) {
+        self.0.zeroize();
+    }
+
+    /// Get the number of characters, for showing a mask
+    pub fn len(&self) -> usize {
+        self.0.chars().count()
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
+
+/// A sudo command that needs the password to be asked for
+#[derive(Debug, Clone, PartialEq)]
+pub struct SudoPrompt {
+    /// The command line to run once the password is typed
+    pub command: String,
+    /// Whether sudo turned down the password it was given
+    pub wrong_password: bool,
+}
+
+/// What became of the password offered to sudo
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum AskpassOutcome {
+    /// sudo didn't ask for a password
+    #[default]
+    NotAsked,
+    /// sudo was given the password
+    Answered,
+    /// sudo asked for a password when there was none to give
+    NoPassword,
+    /// sudo asked again after being given the password, so it was wrong
+    WrongPassword,
+}
+
+/// State shared with the thread answering sudo
+#[derive(Debug, Default)]
+struct AskpassState {
+    outcome: AskpassOutcome,
+    /// Set when the socket is closing, so the thread stops
+    closed: bool,
+}
+
+/// Hands a password to sudo through `SUDO_ASKPASS`, at most once
+///
+/// sudo runs mouse_term as its askpass program, which fetches the password
+/// over a socket in a directory only we can open, so the password is
+/// never written to a pipe or an environment variable. When sudo asks a
+/// second time the password was wrong; it then gets nothing and gives up,
+/// so the password can be asked for again.
+pub struct Askpass {
+    dir: PathBuf,
+    socket: PathBuf,
+    state: Arc<Mutex<AskpassState>>,
+}
+
+impl Askpass {
+    /// Start answering sudo with a password, or with nothing when sudo is
+    /// expected not to need one
+    #[cfg(unix)]
+    pub fn start(password: Option<Secret>) -> io::Result<Self> {
+        use std::os::unix::fs::DirBuilderExt;
+        use std::os::unix::net::UnixListener;
+
+        let dir = std::env::temp_dir().join(format!(
+            "mouse_term-{}-askpass-{}",
+            std::process::id(),
+            ASKPASS_SOCKETS.fetch_add(1, Ordering::Relaxed),
+        ));
+        fs::DirBuilder::new().mode(0o700).create(&dir)?;
+        let socket = dir.join("socket");
+        let listener = UnixListener::bind(&socket)?;
+
+        let state: Arc<Mutex<AskpassState>> = Arc::default();
+        let thread_state = Arc::clone(&state);
+        thread::spawn(move || {
+            let mut password = password;
+            for stream in listener.incoming() {
+                let mut state = thread_state.lock().unwrap_or_else(|e| e.into_inner());
+                if state.closed {
+                    return;
+                }
+                let Ok(mut stream) = stream else {
+                    continue;
+                };
+
+                // The password is wiped as soon as it is sent
+                state.outcome = match (password.take(), state.outcome) {
+                    (Some(secret), _) => {
+                        let _ = stream.write_all(secret.as_bytes());
+                        AskpassOutcome::Answered
+                    }
+                    (None, AskpassOutcome::Answered | AskpassOutcome::WrongPassword) => AskpassOutcome::WrongPassword,
+                    (None, _) => AskpassOutcome::NoPassword,
+                };
+            }
+        });
+
+        Ok(Self { dir, socket, state })
+    }
+
+    /// Start answering sudo with a password
+    #[cfg(not(unix))]
+    pub fn start(_password: Option<Secret>) -> io::Result<Self> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "sudo is not supported on this platform"))
+    }
+
+    /// Get the variables that make a sudo command run with `-A` fetch its
+    /// password from here, in the order of [`ASKPASS_VARS`]
+    pub fn env(&self) -> io::Result<[(String, String); 2]> {
+        let text = |path: PathBuf| {
+            path.into_os_string()
+                .into_string()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "askpass path is not valid UTF-8"))
+        };
+        Ok([
+            (ASKPASS_VARS[0].to_string(), text(std::env::current_exe()?)?),
+            (ASKPASS_VARS[1].to_string(), text(self.socket.clone())?),
+        ])
+    }
+
+    /// Get what became of the password so far
+    pub fn outcome(&self) -> AskpassOutcome {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).outcome
+    }
+}
+
+impl Drop for Askpass {
+    fn drop(&mut self) {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).closed = true;
+
+        // Wake the thread waiting for sudo so it sees the socket is closing
+        #[cfg(unix)]
+        let _ = std::os::unix::net::UnixStream::connect(&self.socket);
+
+        let _ = fs::remove_file(&self.socket);
+        let _ = fs::remove_dir(&self.dir);
+    }
+}
+
+/// Print the password for sudo, when mouse_term runs as its askpass program
+///
+/// Returns the exit code, which is 1 when there is no password to give.
+#[cfg(unix)]
+pub fn run_askpass(socket: &Path) -> i32 {
+    use std::os::unix::net::UnixStream;
+
+    let mut password = Zeroizing::new(Vec::with_capacity(SECRET_CAPACITY * 4));
+    let fetched = UnixStream::connect(socket).and_then(|mut stream| stream.read_to_end(&mut password));
+    if fetched.is_err() || password.is_empty() {
+        return 1;
+    }
+
+    password.push(b'\n');
+    let mut stdout = io::stdout().lock();
+    match stdout.write_all(&password).and_then(|_| stdout.flush()) {
+        Ok(()) => 0,
+        Err(_) => 1,
+    }
+}
+
+/// Print the password for sudo, when mouse_term runs as its askpass program
+#[cfg(not(unix))]
+pub fn run_askpass(_socket: &Path) -> i32 {
+    1
+}
+
+/// Find the index of the program sudo runs, given sudo's arguments
+///
+/// Options and their values are skipped, whether a value is joined to its
+/// option as in `-uroot` or is the next word as in `-u root`.
+pub fn program_index(args: &[String]) -> Option<usize> {
+    let mut idx = 0;
+    while let Some(arg) = args.get(idx) {
+        idx += 1;
+        if arg == "--" {
+            break;
+        }
+        if arg.starts_with("--") {
+            if LONG_VALUE_OPTIONS.contains(&arg.as_str()) {
+                idx += 1;
+            }
+            continue;
+        }
+        let Some(flags) = arg.strip_prefix('-').filter(|flags| !flags.is_empty()) else {
+            return Some(idx - 1);
+        };
+
+        // In a cluster such as `-Eu`, the first option taking a value takes
+        // the rest of the word, or the next word when nothing is left
+        if let Some(pos) = flags.find(VALUE_OPTIONS)
+            && pos + 1 == flags.len()
+        {
+            idx += 1;
+        }
+    }
+    (idx < args.len()).then_some(idx)
+}
+
+/// Run sudo with some options and nothing it could prompt on, returning
+/// whether it succeeded
+///
+/// sudo keeps credentials per terminal, so this runs on mouse_term's own
+/// terminal like the commands run from the password prompt.
+pub fn run_quietly(env: &HashMap<String, String>, args: &[&str]) -> bool {
+    Command::new("sudo")
+        .args(args)
+        .env_clear()
+        .envs(env)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Check whether sudo can run a command without asking for a password
+pub fn credentials_cached(env: &HashMap<String, String>) -> bool {
+    run_quietly(env, &["-n", "true"])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_program_index() {
+        let index = |line: &str| {
+            let args: Vec<String> = line.split_whitespace().map(String::from).collect();
+            program_index(&args).map(|idx| args[idx].clone())
+        };
+        assert_eq!(index("vim f").as_deref(), Some("vim"));
+        assert_eq!(index("-u root vim").as_deref(), Some("vim"));
+        assert_eq!(index("-E nano f").as_deref(), Some("nano"));
+        assert_eq!(index("-uroot -Eg wheel top").as_deref(), Some("top"));
+        assert_eq!(index("--user root --preserve-env --chdir=/tmp htop").as_deref(), Some("htop"));
+        assert_eq!(index("-n -- -weird").as_deref(), Some("-weird"));
+        assert_eq!(index("-u root"), None);
+        assert_eq!(index("-v"), None);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_askpass() {
+        let mut secret = Secret::default();
+        for c in "hunter2é".chars() {
+            secret.push(c);
+        }
+        secret.pop();
+        assert_eq!(secret.len(), 7);
+
+        // The password is given out once, and asking again means it was wrong
+        let askpass = Askpass::start(Some(secret)).unwrap();
+        assert_eq!(askpass.outcome(), AskpassOutcome::NotAsked);
+        let fetch = || {
+            let mut stream = std::os::unix::net::UnixStream::connect(&askpass.socket).unwrap();
+            let mut password = String::new();
+            stream.read_to_string(&mut password).unwrap();
+            password
+        };
+        assert_eq!(fetch(), "hunter2");
+        assert_eq!(askpass.outcome(), AskpassOutcome::Answered);
+        assert_eq!(fetch(), "");
+        assert_eq!(askpass.outcome(), AskpassOutcome::WrongPassword);
+
+        let dir = askpass.dir.clone();
+        drop(askpass);
+        assert!(!dir.exists());
+
+        let askpass = Askpass::start(None).unwrap();
+        assert_eq!(run_askpass(&askpass.socket), 1);
+        assert_eq!(askpass.outcome(), AskpassOutcome::NoPassword);
+    }
+}
@@ -2,15 +2,21 @@ use ratatui::{
     layout::{Constraint, Direction, Layout, Rect, Alignment},
     style::{Color, Modifier, Style},
     text::{Line, Span},
-    widgets::{Block, Borders, BorderType, List, ListItem, Paragraph, Wrap},
+    widgets::{Block, Borders, BorderType, Clear, List, ListItem, Paragraph, Wrap},
     Frame,
 };
 use unicode_width::UnicodeWidthStr;
 use std::path::{Path, PathBuf};
 use std::fs;
 
+use crate::ansi;
+use crate::commands::Suggestions;
+use crate::executor::{OutputLine, Stream};
+use crate::expand;
 use crate::input::InputState;
 use crate::history::History;
+use crate::jobs::{JobState, JobSummary};
+use crate::sudo::Secret;
 
 /// UI state for the application
 pub struct UiState {
@@ -18,8 +24,18 @@ pub struct UiState {
     pub show_history: bool,
     /// Whether a command is currently running
     pub is_running: bool,
-    /// Current output from command execution
-    pub output: Vec<String>,
+    /// The lines of command output that fit in the output pane, in arrival order
+    pub output: Vec<OutputLine>,
+    /// Which lines of `output` changed since a watched command's previous run
+    pub output_changed: Vec<bool>,
+    /// Number of the first line of `output` in the whole output
+    pub output_start: usize,
+    /// Number of lines in the whole output
+    pub output_len: usize,
+    /// How many lines the output pane is scrolled back from the end, 0 to follow new output
+    pub output_scroll: usize,
+    /// Color of lines a command wrote to stderr
+    pub error_color: Color,
     /// Current hover position (token index)
     pub hover_token: Option<usize>,
     /// Currently editing token index
@@ -33,15 +49,47 @@ pub struct UiState {
     /// Whether we're waiting for a sudo password
     pub sudo_password_prompt: bool,
     /// The sudo password being entered
-    pub sudo_password: String,
+    pub sudo_password: Secret,
     /// The command that needs sudo
     pub sudo_command: Option<String>,
+    /// Whether sudo turned down the last password typed
+    pub sudo_wrong_password: bool,
     /// Spinner frame for loading animation
     pub spinner_frame: usize,
     /// Last update time for spinner
     pub last_spinner_update: std::time::Instant,
     /// Whether the UI needs to be refreshed
     pub needs_refresh: bool,
+    /// Preview of what the hovered token expands to
+    pub hover_preview: Option<HoverPreview>,
+    /// Line being typed for the running command's stdin
+    pub stdin_input: String,
+    /// Whether we're asking to confirm quitting while a command runs
+    pub quit_confirm: bool,
+    /// Background jobs shown in the jobs panel
+    pub jobs: Vec<JobSummary>,
+    /// How the last external command ended and what it used
+    pub last_summary: Option<String>,
+    /// Directories saved by `pushd`, most recent first
+    pub dir_stack: Vec<PathBuf>,
+    /// Corrections for the last command if it wasn't found
+    pub suggestions: Option<Suggestions>,
+    /// An env file we're asking whether to trust
+    pub env_trust_prompt: Option<PathBuf>,
+    /// The env files loaded for the current directory, for the status bar
+    pub dir_env: Option<String>,
+}
+
+/// Popup describing what a hovered token would expand to
+pub struct HoverPreview {
+    /// Index of the token the preview belongs to
+    pub token: usize,
+    /// Text of the token when the preview was built
+    pub text: String,
+    /// Title of the popup
+    pub title: String,
+    /// Lines shown in the popup
+    pub lines: Vec<String>,
 }
 
 /// Information about a file or folder
@@ -62,17 +110,32 @@ impl Default for UiState {
             show_history: false,
             is_running: false,
             output: Vec::new(),
+            output_changed: Vec::new(),
+            output_start: 0,
+            output_len: 0,
+            output_scroll: 0,
+            error_color: Color::Red,
             hover_token: None,
             editing_token: None,
             files: Vec::new(),
             current_dir: std::env::current_dir().unwrap_or_else(|_| PathBuf::from("/")),
             hover_file: None,
             sudo_password_prompt: false,
-            sudo_password: String::new(),
+            sudo_password: Secret::default(),
             sudo_command: None,
+            sudo_wrong_password: false,
             spinner_frame: 0,
             last_spinner_update: std::time::Instant::now(),
             needs_refresh: false,
+            hover_preview: None,
+            stdin_input: String::new(),
+            quit_confirm: false,
+            jobs: Vec::new(),
+            last_summary: None,
+            dir_stack: Vec::new(),
+            suggestions: None,
+            env_trust_prompt: None,
+            dir_env: None,
         }
     }
 }
@@ -182,13 +245,34 @@ pub fn render(frame: &mut Frame, ui_state: &mut UiState, input_state: &InputStat
 
     render_output(frame, main_area, ui_state);
     render_status_bar(frame, status_area, ui_state);
-    render_input(frame, input_area, input_state, ui_state);
+
+    // While a command runs, the input line feeds its stdin instead
+    if ui_state.is_running {
+        render_stdin_input(frame, input_area, ui_state);
+    } else {
+        render_input(frame, input_area, input_state, ui_state);
+
+        // Show what the hovered token expands to above the input line
+        if let Some(preview) = &ui_state.hover_preview {
+            render_hover_preview(frame, input_area, preview);
+        }
+    }
 
     // If we're waiting for a sudo password, render the password prompt
     if ui_state.sudo_password_prompt {
         render_sudo_password_prompt(frame, size, ui_state);
     }
 
+    // Ask whether to load an env file found on entering a directory
+    if let Some(file) = &ui_state.env_trust_prompt {
+        render_env_trust_prompt(frame, size, file);
+    }
+
+    // Ask before quitting with a command still running
+    if ui_state.quit_confirm {
+        render_quit_confirm(frame, size);
+    }
+
     // Update spinner frame if command is running
     if ui_state.is_running {
         let now = std::time::Instant::now();
@@ -199,8 +283,13 @@ pub fn render(frame: &mut Frame, ui_state: &mut UiState, input_state: &InputStat
     }
 }
 
-/// Renders the output viewport
-fn render_output(frame: &mut Frame, area: Rect, ui_state: &UiState) {
+/// Split the main viewport into the output pane, the file list, the jobs
+/// panel and the directory stack
+///
+/// The file list is omitted when there is not enough room for both. The jobs
+/// panel shares the file list's row and only appears when `show_jobs` is set.
+/// With `show_dirs`, a line above that row shows the directory stack.
+pub fn split_main_area(area: Rect, show_jobs: bool, show_dirs: bool) -> (Rect, Option<Rect>, Option<Rect>, Option<Rect>) {
     // Ensure minimum heights for output and file list
     let min_output_height = 3;
     let min_file_list_height = 3;
@@ -208,68 +297,226 @@ fn render_output(frame: &mut Frame, area: Rect, ui_state: &UiState) {
     // Calculate available height
     let available_height = area.height;
 
-    // If we have enough space for both sections with minimum heights
-    if available_height >= min_output_height + min_file_list_height {
-        // Calculate output height (60% of available space, but at least min_output_height)
-        let output_height = std::cmp::max(
-            min_output_height,
-            (available_height as f32 * 0.6) as u16
-        );
+    // Not enough space for both sections, just show output
+    if available_height < min_output_height + min_file_list_height {
+        return (area, None, None, None);
+    }
 
-        // Calculate file list height (remaining space, but at least min_file_list_height)
-        let file_list_height = std::cmp::max(
-            min_file_list_height,
-            available_height.saturating_sub(output_height)
-        );
+    // Calculate output height (60% of available space, but at least min_output_height)
+    let output_height = std::cmp::max(
+        min_output_height,
+        (available_height as f32 * 0.6) as u16
+    );
+
+    // Calculate file list height (remaining space, but at least min_file_list_height)
+    let file_list_height = std::cmp::max(
+        min_file_list_height,
+        available_height.saturating_sub(output_height)
+    );
 
-        // Split the area into two parts: output and file list
-        let chunks = Layout::default()
+    // Split the area into two parts: output and file list
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Min(output_height),     // Command output
+            Constraint::Min(file_list_height),  // File list
+        ])
+        .split(area);
+
+    // Give the directory stack a line of its own when there is room
+    let (file_row, dirs_area) = if show_dirs && chunks[1].height > min_file_list_height {
+        let rows = Layout::default()
             .direction(Direction::Vertical)
             .constraints([
-                Constraint::Min(output_height),     // Command output
-                Constraint::Min(file_list_height),  // File list
+                Constraint::Length(1), // Directory stack
+                Constraint::Min(0),    // File list and jobs
             ])
-            .split(area);
-
-        // Render command output
-        let output_text: Vec<String> = ui_state.output
-            .iter()
-            .map(|line| line.clone())
-            .collect();
-
-        let output_widget = Paragraph::new(output_text.join("\n"))
-            .block(Block::default()
-                .borders(Borders::ALL)
-                .border_type(BorderType::Rounded)
-                .border_style(Style::default().fg(Color::Cyan))
-                .title(format!(" 📺 Output - {} ", ui_state.current_dir.display()))
-                .title_style(Style::default().fg(Color::White).add_modifier(Modifier::BOLD)))
-            .wrap(Wrap { trim: true });
-
-        frame.render_widget(output_widget, chunks[0]);
-
-        // Render file list
-        render_file_list(frame, chunks[1], ui_state);
+            .split(chunks[1]);
+        (rows[1], Some(rows[0]))
     } else {
-        // Not enough space for both sections, just show output
-        let output_text: Vec<String> = ui_state.output
-            .iter()
-            .map(|line| line.clone())
-            .collect();
-
-        let output_widget = Paragraph::new(output_text.join("\n"))
-            .block(Block::default()
-                .borders(Borders::ALL)
-                .border_type(BorderType::Rounded)
-                .border_style(Style::default().fg(Color::Cyan))
-                .title(format!(" 📺 Output - {} ", ui_state.current_dir.display()))
-                .title_style(Style::default().fg(Color::White).add_modifier(Modifier::BOLD)))
-            .wrap(Wrap { trim: true });
-
-        frame.render_widget(output_widget, area);
+        (chunks[1], None)
+    };
+
+    if !show_jobs {
+        return (chunks[0], Some(file_row), None, dirs_area);
+    }
+
+    // Give the jobs panel 40% of the row, minimum 24 columns
+    let jobs_width = std::cmp::max(24, (area.width as f32 * 0.4) as u16).min(area.width);
+    let row = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Min(area.width.saturating_sub(jobs_width)), // File list
+            Constraint::Length(jobs_width),                         // Jobs
+        ])
+        .split(file_row);
+
+    (chunks[0], Some(row[0]), Some(row[1]), dirs_area)
+}
+
+/// Renders the output viewport
+fn render_output(frame: &mut Frame, area: Rect, ui_state: &UiState) {
+    let (output_area, file_list_area, jobs_area, dirs_area) =
+        split_main_area(area, !ui_state.jobs.is_empty(), !ui_state.dir_stack.is_empty());
+
+    // Say which job's output is shown when it isn't the foreground one
+    let mut title = match ui_state.jobs.iter().find(|job| job.selected) {
+        Some(job) => format!(" 📺 Output - job [{}] {} ", job.number, job.command),
+        None => format!(" 📺 Output - {} ", ui_state.current_dir.display()),
+    };
+
+    // Say where the view is when scrolled back
+    if ui_state.output_scroll > 0 {
+        title.push_str(&format!(
+            "· lines {}-{} of {} ",
+            ui_state.output_start + 1,
+            ui_state.output_start + ui_state.output.len(),
+            ui_state.output_len,
+        ));
+    }
+
+    // Render command output with its own colors, and stderr in the error
+    // color, highlighting what a watched command changed
+    let lines: Vec<Line> = ui_state.output.iter()
+        .enumerate()
+        .map(|(idx, line)| {
+            let base = match line.stream {
+                Stream::Stdout => Style::default(),
+                Stream::Stderr => Style::default().fg(ui_state.error_color),
+            };
+            let mut styled = ansi::styled_line(&line.text, base);
+            if ui_state.output_changed.get(idx) == Some(&true) {
+                styled.patch_style(Style::default().add_modifier(Modifier::REVERSED));
+            }
+            styled
+        })
+        .collect();
+
+    // Keep the last line at the bottom of the pane when long lines wrap
+    let inner_width = usize::from(output_area.width.saturating_sub(2)).max(1);
+    let rows: usize = lines.iter().map(|line| line.width().div_ceil(inner_width).max(1)).sum();
+    let overflow = rows.saturating_sub(usize::from(output_area.height.saturating_sub(2)));
+
+    let output_widget = Paragraph::new(lines)
+        .block(Block::default()
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .border_style(Style::default().fg(Color::Cyan))
+            .title(title)
+            .title_style(Style::default().fg(Color::White).add_modifier(Modifier::BOLD)))
+        .wrap(Wrap { trim: true })
+        .scroll((u16::try_from(overflow).unwrap_or(u16::MAX), 0));
+
+    frame.render_widget(output_widget, output_area);
+
+    // Offer corrections for a command that wasn't found on the bottom border
+    if let Some(suggestions) = &ui_state.suggestions
+        && output_area.height > 2
+    {
+        render_suggestions(frame, output_area, suggestions);
+    }
+
+    // Render file list
+    if let Some(file_list_area) = file_list_area {
+        render_file_list(frame, file_list_area, ui_state);
+    }
+
+    // Render jobs panel
+    if let Some(jobs_area) = jobs_area {
+        render_jobs(frame, jobs_area, ui_state);
+    }
+
+    // Render directory stack
+    if let Some(dirs_area) = dirs_area {
+        render_dir_stack(frame, dirs_area, ui_state);
     }
 }
 
+/// Label shown before corrections for a missing command
+const SUGGESTIONS_LABEL: &str = " did you mean: ";
+
+/// Renders corrections for a missing command over the output pane's bottom border
+fn render_suggestions(frame: &mut Frame, output_area: Rect, suggestions: &Suggestions) {
+    let mut spans = vec![Span::styled(SUGGESTIONS_LABEL, Style::default().fg(Color::White))];
+    for candidate in &suggestions.candidates {
+        spans.push(Span::styled(
+            format!(" {} ", candidate),
+            Style::default().fg(Color::Yellow).add_modifier(Modifier::UNDERLINED | Modifier::BOLD),
+        ));
+        spans.push(Span::raw(" "));
+    }
+
+    let area = Rect::new(
+        output_area.x + 2,
+        output_area.y + output_area.height - 1,
+        output_area.width.saturating_sub(4),
+        1,
+    );
+    frame.render_widget(Paragraph::new(Line::from(spans)), area);
+}
+
+/// Label shown before the directory stack
+const DIR_STACK_LABEL: &str = " 📚 pushd: ";
+
+/// Get the entries of the directory stack line, current directory first
+fn dir_stack_entries(ui_state: &UiState) -> Vec<String> {
+    let home = dirs::home_dir();
+    std::iter::once(&ui_state.current_dir)
+        .chain(&ui_state.dir_stack)
+        .enumerate()
+        .map(|(idx, dir)| format!(" {} {} ", idx, expand::collapse_home(dir, home.as_deref())))
+        .collect()
+}
+
+/// Renders the directory stack line
+fn render_dir_stack(frame: &mut Frame, area: Rect, ui_state: &UiState) {
+    let mut spans = vec![Span::styled(DIR_STACK_LABEL, Style::default().fg(Color::White).add_modifier(Modifier::BOLD))];
+
+    for (idx, entry) in dir_stack_entries(ui_state).into_iter().enumerate() {
+        // The current directory is highlighted, the others can be clicked
+        let style = if idx == 0 {
+            Style::default().fg(Color::Black).bg(Color::Yellow)
+        } else {
+            Style::default().fg(Color::Yellow).add_modifier(Modifier::UNDERLINED)
+        };
+        spans.push(Span::styled(entry, style));
+        spans.push(Span::raw(" "));
+    }
+
+    frame.render_widget(Paragraph::new(Line::from(spans)), area);
+}
+
+/// Renders the jobs panel
+fn render_jobs(frame: &mut Frame, area: Rect, ui_state: &UiState) {
+    let items: Vec<ListItem> = ui_state.jobs.iter()
+        .map(|job| {
+            let color = match job.state {
+                JobState::Running => Color::Green,
+                JobState::Stopped => Color::Yellow,
+                JobState::Done(Some(0)) => Color::Gray,
+                JobState::Done(_) => Color::Red,
+            };
+            let mut style = Style::default().fg(color);
+            if job.selected {
+                style = style.add_modifier(Modifier::REVERSED);
+            }
+
+            let text = format!("[{}] {:<10} {}", job.number, job.state.to_string(), job.command);
+            ListItem::new(Line::from(vec![Span::styled(text, style)]))
+        })
+        .collect();
+
+    let jobs_list = List::new(items)
+        .block(Block::default()
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .border_style(Style::default().fg(Color::Magenta))
+            .title(" ⚙ Jobs (click to view) ")
+            .title_style(Style::default().fg(Color::White).add_modifier(Modifier::BOLD)));
+
+    frame.render_widget(jobs_list, area);
+}
+
 /// Renders the file list
 fn render_file_list(frame: &mut Frame, area: Rect, ui_state: &UiState) {
     let mut items = Vec::new();
@@ -363,6 +610,52 @@ fn render_input(frame: &mut Frame, area: Rect, input_state: &InputState, ui_stat
     frame.render_widget(input_widget, area);
 }
 
+/// Renders the line being typed for the running command's stdin
+fn render_stdin_input(frame: &mut Frame, area: Rect, ui_state: &UiState) {
+    let spans = vec![
+        Span::raw(ui_state.stdin_input.clone()),
+        Span::styled("|", Style::default().fg(Color::Yellow)),
+    ];
+
+    let input_widget = Paragraph::new(Line::from(spans))
+        .block(Block::default()
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .border_style(Style::default().fg(Color::Magenta))
+            .title(" ⌨ Input to command (Enter: send, Ctrl+D: end of input) ")
+            .title_style(Style::default().fg(Color::White).add_modifier(Modifier::BOLD)));
+
+    frame.render_widget(input_widget, area);
+}
+
+/// Renders the hover preview popup just above the input line
+fn render_hover_preview(frame: &mut Frame, input_area: Rect, preview: &HoverPreview) {
+    // Size the popup to its content, within the space above the input line
+    let content_width = preview.lines.iter()
+        .map(|line| line.width())
+        .chain(std::iter::once(preview.title.width()))
+        .max()
+        .unwrap_or(0) as u16;
+    let width = std::cmp::min(content_width + 2, input_area.width);
+    let height = std::cmp::min(preview.lines.len() as u16 + 2, input_area.y);
+    if height < 3 {
+        return;
+    }
+
+    let area = Rect::new(input_area.x, input_area.y - height, width, height);
+
+    let preview_widget = Paragraph::new(preview.lines.join("\n"))
+        .block(Block::default()
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .border_style(Style::default().fg(Color::Blue))
+            .title(preview.title.as_str())
+            .title_style(Style::default().fg(Color::White).add_modifier(Modifier::BOLD)));
+
+    frame.render_widget(Clear, area);
+    frame.render_widget(preview_widget, area);
+}
+
 /// Renders the history sidebar
 fn render_history(frame: &mut Frame, area: Rect, history: &History) {
     let history_items: Vec<ListItem> = history.commands
@@ -412,6 +705,61 @@ pub fn get_token_at_position(
     None
 }
 
+/// Determines which directory stack entry was clicked based on mouse coordinates
+///
+/// Returns the entry's position in the stack, with the current directory at 0.
+pub fn get_dir_at_position(
+    ui_state: &UiState,
+    x: u16,
+    dirs_area: Rect,
+) -> Option<usize> {
+    let effective_x = x.checked_sub(dirs_area.x)? as usize;
+    let mut current_pos = DIR_STACK_LABEL.width();
+
+    for (idx, entry) in dir_stack_entries(ui_state).iter().enumerate() {
+        let entry_width = entry.width();
+        if effective_x >= current_pos && effective_x < current_pos + entry_width {
+            return Some(idx);
+        }
+
+        // Move past this entry and the space after it
+        current_pos += entry_width + 1;
+    }
+
+    None
+}
+
+/// Determines which correction for a missing command was clicked
+///
+/// Returns the candidate's position in the list of suggestions.
+pub fn get_suggestion_at_position(
+    ui_state: &UiState,
+    x: u16,
+    y: u16,
+    output_area: Rect,
+) -> Option<usize> {
+    let suggestions = ui_state.suggestions.as_ref()?;
+    if output_area.height <= 2 || y != output_area.y + output_area.height - 1 {
+        return None;
+    }
+
+    let effective_x = x.checked_sub(output_area.x + 2)? as usize;
+    let mut current_pos = SUGGESTIONS_LABEL.width();
+
+    for (idx, candidate) in suggestions.candidates.iter().enumerate() {
+        // Each candidate is padded with a space on either side
+        let candidate_width = candidate.width() + 2;
+        if effective_x >= current_pos && effective_x < current_pos + candidate_width {
+            return Some(idx);
+        }
+
+        // Move past this candidate and the space after it
+        current_pos += candidate_width + 1;
+    }
+
+    None
+}
+
 /// Determines which file was clicked based on mouse coordinates
 pub fn get_file_at_position(
     ui_state: &UiState,
@@ -436,18 +784,33 @@ pub fn get_file_at_position(
     }
 }
 
+/// Determines which job was clicked based on mouse coordinates
+///
+/// Returns the job number.
+pub fn get_job_at_position(
+    ui_state: &UiState,
+    y: u16,
+    jobs_area: Rect,
+) -> Option<usize> {
+    // Skip the top border
+    if y <= jobs_area.y || y >= jobs_area.y + jobs_area.height {
+        return None;
+    }
+
+    let idx = (y - jobs_area.y - 1) as usize;
+    ui_state.jobs.get(idx).map(|job| job.number)
+}
+
 /// Update the file list based on the current directory
 pub fn update_file_list(ui_state: &mut UiState) -> anyhow::Result<()> {
     let current_dir = &ui_state.current_dir;
     let mut files = Vec::new();
 
     // Read the directory entries
-    for entry in fs::read_dir(current_dir)? {
-        if let Ok(entry) = entry {
-            let path = entry.path();
-            let file_info = FileInfo::from_path(&path);
-            files.push(file_info);
-        }
+    for entry in fs::read_dir(current_dir)?.flatten() {
+        let path = entry.path();
+        let file_info = FileInfo::from_path(&path);
+        files.push(file_info);
     }
 
     // Sort directories first, then by name
@@ -497,15 +860,29 @@ fn render_status_bar(frame: &mut Frame, area: Rect, ui_state: &UiState) {
 
     // Create status line
     let left_part = format!(" {} {}", spinner, breadcrumb);
-    let right_part = format!("{} ", time_str);
+    let mut right_part = format!("{} ", time_str);
+
+    // Show that variables from the directory's env files are loaded
+    let dir_env_part = ui_state.dir_env.as_ref()
+        .map(|dir_env| format!(" env: {} ", dir_env))
+        .unwrap_or_default();
+
+    // Show how the last command went, when there is room for it
+    if let Some(summary) = &ui_state.last_summary {
+        let with_summary = format!("{}  {} ", summary, time_str);
+        if left_part.width() + dir_env_part.width() + with_summary.width() < area.width as usize {
+            right_part = with_summary;
+        }
+    }
 
     // Calculate padding
-    let padding_len = area.width as usize - left_part.len() - right_part.len();
+    let padding_len = (area.width as usize).saturating_sub(left_part.width() + dir_env_part.width() + right_part.width());
     let padding = " ".repeat(padding_len);
 
     // Create spans
     let spans = vec![
         Span::styled(left_part, Style::default().fg(Color::White).bg(Color::Blue)),
+        Span::styled(dir_env_part, Style::default().fg(Color::Black).bg(Color::Green)),
         Span::styled(padding, Style::default().bg(Color::DarkGray)),
         Span::styled(right_part, Style::default().fg(Color::White).bg(Color::Blue)),
     ];
@@ -517,6 +894,60 @@ fn render_status_bar(frame: &mut Frame, area: Rect, ui_state: &UiState) {
     frame.render_widget(status_bar, area);
 }
 
+/// Renders the confirmation shown when quitting with a command running
+fn render_quit_confirm(frame: &mut Frame, size: Rect) {
+    let width = 50.min(size.width);
+    let height = 5.min(size.height);
+    let area = Rect::new(
+        (size.width.saturating_sub(width)) / 2,
+        (size.height.saturating_sub(height)) / 2,
+        width,
+        height,
+    );
+
+    let confirm_widget = Paragraph::new("A command is still running and will be killed.\n\nPress y to quit or any other key to stay")
+        .block(Block::default()
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .border_style(Style::default().fg(Color::Red))
+            .title(" Quit mouse_term? ")
+            .title_style(Style::default().fg(Color::White).add_modifier(Modifier::BOLD)))
+        .style(Style::default().fg(Color::White))
+        .wrap(Wrap { trim: true });
+
+    frame.render_widget(Clear, area);
+    frame.render_widget(confirm_widget, area);
+}
+
+/// Renders the question whether to load an env file
+fn render_env_trust_prompt(frame: &mut Frame, size: Rect, file: &Path) {
+    let width = 60.min(size.width);
+    let height = 7.min(size.height);
+    let area = Rect::new(
+        (size.width.saturating_sub(width)) / 2,
+        (size.height.saturating_sub(height)) / 2,
+        width,
+        height,
+    );
+
+    let text = format!(
+        "{} sets variables for this directory.\n\nPress y to load it whenever you are here, n to never load it, or any other key to decide later",
+        file.display(),
+    );
+    let prompt_widget = Paragraph::new(text)
+        .block(Block::default()
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .border_style(Style::default().fg(Color::Yellow))
+            .title(" Trust this env file? ")
+            .title_style(Style::default().fg(Color::White).add_modifier(Modifier::BOLD)))
+        .style(Style::default().fg(Color::White))
+        .wrap(Wrap { trim: true });
+
+    frame.render_widget(Clear, area);
+    frame.render_widget(prompt_widget, area);
+}
+
 /// Renders the sudo password prompt
 fn render_sudo_password_prompt(frame: &mut Frame, size: Rect, ui_state: &UiState) {
     // Create a semi-transparent overlay for the entire screen
@@ -526,16 +957,10 @@ fn render_sudo_password_prompt(frame: &mut Frame, size: Rect, ui_state: &UiState
 
     // Calculate adaptive dimensions for the password prompt
     // Width: 50% of screen width, but at least 40 columns and at most 80 columns
-    let width = std::cmp::min(
-        80,
-        std::cmp::max(40, (size.width as f32 * 0.5) as u16)
-    );
+    let width = ((size.width as f32 * 0.5) as u16).clamp(40, 80);
 
     // Height: 30% of screen height, but at least 5 rows and at most 10 rows
-    let height = std::cmp::min(
-        10,
-        std::cmp::max(5, (size.height as f32 * 0.3) as u16)
-    );
+    let height = ((size.height as f32 * 0.3) as u16).clamp(5, 10);
 
     // Ensure the prompt fits on screen
     let width = std::cmp::min(width, size.width.saturating_sub(4));
@@ -549,11 +974,12 @@ fn render_sudo_password_prompt(frame: &mut Frame, size: Rect, ui_state: &UiState
 
     // Create the password field with masked input
     let masked_password = "*".repeat(ui_state.sudo_password.len());
-    let cursor = if ui_state.spinner_frame % 2 == 0 { "█" } else { " " }; // Blinking cursor
+    let cursor = if ui_state.spinner_frame.is_multiple_of(2) { "█" } else { " " }; // Blinking cursor
     let password_text = format!("Password: {}{}", masked_password, cursor);
 
-    // Add instructions
-    let instructions = "\n\nPress Enter to submit or Esc to cancel";
+    // Add instructions, after saying why the password is asked for again
+    let retry = if ui_state.sudo_wrong_password { "\nSorry, try again." } else { "" };
+    let instructions = format!("{}\n\nPress Enter to submit or Esc to cancel", retry);
 
     let password_widget = Paragraph::new(format!("{}{}", password_text, instructions))
         .block(Block::default()
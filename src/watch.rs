@@ -0,0 +1,225 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::path::{Component, Path};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::events::Notifier;
+
+/// How often a watched command runs when `watch` isn't given an interval
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Directories whose changes don't run a watched command again, since
+/// build tools and version control write to them all the time
+const IGNORED_DIRS: &[&str] = &[".git", "target", "node_modules"];
+
+/// What runs a watched command again
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trigger {
+    /// Every interval, counted from the end of the previous run
+    Interval(Duration),
+    /// Whenever a file under the current directory changes
+    Files,
+}
+
+/// Notices changes to the files under a directory
+pub struct FileWatcher {
+    /// Stops watching when dropped
+    _watcher: RecommendedWatcher,
+    /// Whether a file changed since the last check
+    changed: Arc<AtomicBool>,
+}
+
+impl FileWatcher {
+    /// Start watching everything under `dir`, waking the main loop through
+    /// `notifier` when something changes
+    pub fn start(dir: &Path, notifier: Notifier) -> notify::Result<Self> {
+        let changed = Arc::new(AtomicBool::new(false));
+        let watcher_changed = Arc::clone(&changed);
+        let root = dir.to_path_buf();
+
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            let Ok(event) = event else {
+                return;
+            };
+            if event.kind.is_access() {
+                return;
+            }
+
+            let ignored = |path: &Path| {
+                path.strip_prefix(&root).unwrap_or(path).components().any(|component| {
+                    matches!(component, Component::Normal(name) if IGNORED_DIRS.iter().any(|ignored| name == *ignored))
+                })
+            };
+            if event.paths.iter().any(|path| !ignored(path)) && !watcher_changed.swap(true, Ordering::SeqCst) {
+                notifier.notify();
+            }
+        })?;
+        watcher.watch(dir, RecursiveMode::Recursive)?;
+
+        Ok(Self { _watcher: watcher, changed })
+    }
+
+    /// Check whether a file changed since the last check
+    fn take_change(&self) -> bool {
+        self.changed.swap(false, Ordering::SeqCst)
+    }
+}
+
+/// A command line run again and again by `watch`
+///
+/// Each run replaces the output, and lines that weren't in the output of
+/// the run before are highlighted.
+pub struct Watch {
+    /// The command line, expanded afresh for each run
+    pub command: String,
+    /// What runs the command again
+    pub trigger: Trigger,
+    /// Notices file changes, for [`Trigger::Files`]
+    files: Option<FileWatcher>,
+    /// How many runs have started
+    runs: usize,
+    /// Whether a run has started and wasn't seen to finish yet
+    running: bool,
+    /// When the next run is due, if it waits for a time
+    next_run: Option<Instant>,
+    /// Lines of the run before the one shown
+    baseline: HashSet<String>,
+    /// Lines of the last finished run
+    last_run: HashSet<String>,
+}
+
+impl Watch {
+    /// Start watching a command line, running it straight away
+    pub fn new(command: String, trigger: Trigger, files: Option<FileWatcher>) -> Self {
+        Self {
+            command,
+            trigger,
+            files,
+            runs: 0,
+            running: false,
+            next_run: Some(Instant::now()),
+            baseline: HashSet::new(),
+            last_run: HashSet::new(),
+        }
+    }
+
+    /// Check whether a run was started and not seen to finish yet
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Check whether the next run is due
+    pub fn is_due(&self, now: Instant) -> bool {
+        !self.running
+            && (self.next_run.is_some_and(|next_run| next_run <= now)
+                || self.files.as_ref().is_some_and(FileWatcher::take_change))
+    }
+
+    /// Get when the main loop has to wake up for the next run
+    pub fn deadline(&self) -> Option<Instant> {
+        self.next_run.filter(|_| !self.running)
+    }
+
+    /// Start a run, returning the header line shown above its output
+    pub fn start_run(&mut self) -> String {
+        self.runs += 1;
+        self.running = true;
+        self.next_run = None;
+        self.baseline = std::mem::take(&mut self.last_run);
+
+        let trigger = match self.trigger {
+            Trigger::Interval(interval) => format!("Every {}", humanize(interval)),
+            Trigger::Files => "On file changes".to_string(),
+        };
+        format!(
+            "{}: {} · run {} at {}",
+            trigger,
+            self.command,
+            self.runs,
+            chrono::Local::now().format("%H:%M:%S"),
+        )
+    }
+
+    /// Record the output of a finished run and schedule the next one
+    ///
+    /// Files the run changed itself, as builds and tests do, don't count
+    /// as changes.
+    pub fn finish_run(&mut self, lines: impl IntoIterator<Item = String>) {
+        self.running = false;
+        self.last_run = lines.into_iter().map(|line| comparable(&line).into_owned()).collect();
+        self.next_run = match self.trigger {
+            Trigger::Interval(interval) => Some(Instant::now() + interval),
+            Trigger::Files => None,
+        };
+        if let Some(files) = &self.files {
+            files.take_change();
+        }
+    }
+
+    /// Check whether a line of the shown run wasn't in the run before
+    pub fn is_changed(&self, text: &str) -> bool {
+        self.runs > 1 && !self.baseline.contains(comparable(text).as_ref())
+    }
+}
+
+/// Get the part of a line that is compared between runs
+///
+/// The time and resources in a summary line such as
+/// `[exit 0 · 0.02s · user 0.01s · sys 0.00s · max RSS 2.1 MB] make` differ
+/// on every run, so only the exit status and what follows are compared.
+fn comparable(text: &str) -> Cow<'_, str> {
+    let Some((summary, rest)) = text.strip_prefix('[').and_then(|text| text.split_once(']')) else {
+        return Cow::Borrowed(text);
+    };
+    match summary.split_once(" · ") {
+        Some((status, usage)) if usage.contains(" · max RSS ") => Cow::Owned(format!("[{}]{}", status, rest)),
+        _ => Cow::Borrowed(text),
+    }
+}
+
+/// Format an interval the way it could be typed, such as `2s` or `500ms`
+fn humanize(interval: Duration) -> String {
+    if interval.subsec_millis() == 0 {
+        format!("{}s", interval.as_secs())
+    } else {
+        format!("{}ms", interval.as_millis())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watch_runs() {
+        let mut watch = Watch::new("kubectl get pods".to_string(), Trigger::Interval(Duration::from_millis(500)), None);
+        let now = Instant::now();
+        assert!(watch.is_due(now));
+
+        assert!(watch.start_run().starts_with("Every 500ms: kubectl get pods · run 1 at "));
+        assert!(!watch.is_due(now + Duration::from_secs(10)));
+        assert!(watch.deadline().is_none());
+        // Nothing is highlighted on the first run
+        assert!(!watch.is_changed("web-1 Running"));
+
+        watch.finish_run([
+            "web-1 Running".to_string(),
+            "web-2 Pending".to_string(),
+            "[exit 0 · 0.31s · user 0.12s · sys 0.02s · max RSS 41.0 MB]".to_string(),
+        ]);
+        let deadline = watch.deadline().unwrap();
+        assert!(!watch.is_due(deadline - Duration::from_millis(100)));
+        assert!(watch.is_due(deadline));
+
+        watch.start_run();
+        assert!(!watch.is_changed("web-1 Running"));
+        assert!(watch.is_changed("web-2 Running"));
+        // Only the status of a summary line counts
+        assert!(!watch.is_changed("[exit 0 · 0.28s · user 0.11s · sys 0.03s · max RSS 40.2 MB]"));
+        assert!(watch.is_changed("[exit 1 · 0.28s · user 0.11s · sys 0.03s · max RSS 40.2 MB]"));
+    }
+}
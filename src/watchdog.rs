@@ -0,0 +1,166 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+use crate::executor::PipelineEvent;
+use crate::process::Signal;
+
+/// How long a pipeline gets to exit after SIGTERM before it is killed
+const KILL_GRACE: Duration = Duration::from_secs(5);
+
+/// Limits a pipeline is stopped at
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Limits {
+    /// How long the pipeline may run, and where that limit was set
+    pub timeout: Option<(Duration, String)>,
+    /// How many lines of output the pipeline may produce
+    pub max_output_lines: Option<usize>,
+}
+
+/// Counts the lines a pipeline outputs, shared by its reader threads
+#[derive(Debug, Clone, Default)]
+pub struct OutputCounter {
+    lines: Arc<AtomicUsize>,
+    max: Option<usize>,
+    /// Told once when the limit is passed, so the watchdog needn't keep checking
+    supervisor: Option<Sender<PipelineEvent>>,
+}
+
+impl OutputCounter {
+    /// Count a complete line, returning whether it is within the limit
+    pub fn count_line(&self) -> bool {
+        let count = self.lines.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(supervisor) = &self.supervisor
+            && self.max.is_some_and(|max| count == max + 1)
+        {
+            let _ = supervisor.send(PipelineEvent::OutputLimit);
+        }
+        self.max.is_none_or(|max| count <= max)
+    }
+
+    /// Check whether more output may still be shown
+    pub fn within_limit(&self) -> bool {
+        self.max.is_none_or(|max| self.lines.load(Ordering::Relaxed) < max)
+    }
+
+    /// Check whether the pipeline has output more lines than allowed
+    fn exceeded(&self) -> bool {
+        self.max.is_some_and(|max| self.lines.load(Ordering::Relaxed) > max)
+    }
+}
+
+/// Stops a pipeline that runs past its limits
+///
+/// The stages get SIGTERM when a limit is first reached, and SIGKILL if
+/// they are still running a few seconds later. The thread supervising the
+/// pipeline calls `check` when the output limit is passed and at each
+/// `deadline`.
+#[derive(Debug)]
+pub struct Watchdog {
+    limits: Limits,
+    started: Instant,
+    counter: OutputCounter,
+    /// When SIGTERM was sent because a limit was reached
+    terminated: Option<Instant>,
+    killed: bool,
+}
+
+impl Watchdog {
+    /// Start watching a pipeline from now, telling `supervisor` when the output limit is passed
+    pub fn new(limits: Limits, supervisor: Sender<PipelineEvent>) -> Self {
+        let counter = OutputCounter {
+            lines: Arc::default(),
+            max: limits.max_output_lines,
+            supervisor: Some(supervisor),
+        };
+
+        Self {
+            limits,
+            started: Instant::now(),
+            counter,
+            terminated: None,
+            killed: false,
+        }
+    }
+
+    /// Get a counter for a reader thread of the pipeline
+    pub fn counter(&self) -> OutputCounter {
+        self.counter.clone()
+    }
+
+    /// Get the next time `check` may have a signal to send, if there is one
+    pub fn deadline(&self) -> Option<Instant> {
+        match self.terminated {
+            None => self.limits.timeout.as_ref().map(|(limit, _)| self.started + *limit),
+            Some(at) if !self.killed => Some(at + KILL_GRACE),
+            Some(_) => None,
+        }
+    }
+
+    /// Check the limits, returning a signal to send and a line saying why
+    pub fn check(&mut self) -> Option<(Signal, String)> {
+        match self.terminated {
+            None => {
+                let reason = match &self.limits.timeout {
+                    Some((limit, source)) if self.started.elapsed() >= *limit => {
+                        format!("time limit of {:?} reached ({})", limit, source)
+                    }
+                    _ if self.counter.exceeded() => {
+                        format!("output limit of {} lines reached (execution.max_output_lines)", self.limits.max_output_lines.unwrap_or_default())
+                    }
+                    _ => return None,
+                };
+
+                self.terminated = Some(Instant::now());
+                Some((Signal::Terminate, format!("[{}, sending SIGTERM]", reason)))
+            }
+            Some(at) if !self.killed && at.elapsed() >= KILL_GRACE => {
+                self.killed = true;
+                Some((Signal::Kill, format!("[still running {:?} after SIGTERM, sending SIGKILL]", KILL_GRACE)))
+            }
+            Some(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watchdog_limits() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut watchdog = Watchdog::new(Limits {
+            timeout: Some((Duration::from_millis(20), "execution.timeout".to_string())),
+            max_output_lines: None,
+        }, tx.clone());
+        assert_eq!(watchdog.check(), None);
+        assert_eq!(watchdog.deadline(), Some(watchdog.started + Duration::from_millis(20)));
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(watchdog.check(), Some((
+            Signal::Terminate,
+            "[time limit of 20ms reached (execution.timeout), sending SIGTERM]".to_string(),
+        )));
+        // Only once, and the kill waits for the grace period
+        assert_eq!(watchdog.check(), None);
+        assert!(watchdog.deadline().is_some_and(|deadline| deadline > Instant::now() + Duration::from_secs(4)));
+
+        let mut watchdog = Watchdog::new(Limits {
+            timeout: None,
+            max_output_lines: Some(2),
+        }, tx);
+        assert_eq!(watchdog.deadline(), None);
+        let counter = watchdog.counter();
+        assert!(counter.count_line() && counter.count_line());
+        assert!(!counter.within_limit());
+        assert_eq!(watchdog.check(), None);
+        assert!(rx.try_recv().is_err());
+        assert!(!counter.count_line() && !counter.count_line());
+        // The supervisor hears about it once
+        assert!(matches!(rx.try_recv(), Ok(PipelineEvent::OutputLimit)));
+        assert!(rx.try_recv().is_err());
+        assert_eq!(watchdog.check().map(|(signal, _)| signal), Some(Signal::Terminate));
+    }
+}